@@ -20,10 +20,9 @@ fn main() {
         "#  #  ### # #  ##        # #    ##  #    #  ## ",
     ];
 
-    for y in 0..hello_world.len() {
-        let string = hello_world[y];
-        for x in 0..string.len() {
-            if string.as_bytes()[x] == '#' as u8 {
+    for (y, string) in hello_world.iter().enumerate() {
+        for (x, &c) in string.as_bytes().iter().enumerate() {
+            if c == b'#' {
                 block.position = [x as f32, 0f32 - (y as f32), 0f32];
 
                 building.blocks.push(block.clone());
@@ -0,0 +1,43 @@
+use std::process::ExitCode;
+
+use sw_structure_io::io::byte_map;
+
+const USAGE: &str = "\
+usage: sw-structure <command> <file>
+
+commands:
+  bytemap  print which field every byte of the file belongs to";
+
+fn main() -> ExitCode {
+    env_logger::init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [command, path] = args.as_slice() else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("can't read {path}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match command.as_str() {
+        "bytemap" => {
+            let map = byte_map(&data);
+            print!("{map}");
+            if map.error.is_some() {
+                return ExitCode::FAILURE;
+            }
+        }
+        _ => {
+            eprintln!("unknown command {command:?}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,133 @@
+//! Catalog of block types.
+//!
+//! Block types are identified by a numeric id in serialized files. [`BlockKind`]
+//! wraps that id and answers what is known about it. Any `u8` is a valid
+//! `BlockKind`, so ids this catalog doesn't know about survive a round trip
+//! unchanged.
+//!
+//! Only facts that were confirmed against game files are recorded here. Where a
+//! property of a block type isn't known yet, the accessor returns `None`.
+//! [`BlockKind::known`] lists every id something is recorded for.
+//!
+//! The catalog is not complete: a name and load capability are only recorded for
+//! the math block. Every other id, the custom blocks included, has no name and
+//! unknown load capability until game files that contain each block type confirm
+//! them. For the same reason the [simulator](crate::sim) has no built-in
+//! behaviours for gates and sensors.
+
+use std::fmt;
+
+/// Numeric block type identifier.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct BlockKind(pub u8);
+
+/// Which [`TypeSettings`](crate::structs::TypeSettings) variant a block type uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TypeSettingsKind {
+    /// The block type has no advanced settings.
+    None,
+    /// [`TypeSettings::MathBlock`](crate::structs::TypeSettings::MathBlock).
+    MathBlock,
+    /// The block type has advanced settings whose layout isn't known yet.
+    Unknown,
+}
+
+/// Block types that can't be interacted with (decorative and structural blocks).
+const NOT_INTERACTABLE: [u8; 27] = [
+    0, 1, 28, 33, 34, 35, 36, 37, 38,
+    59, 62, 63, 64, 65, 66, 67, 68, 69,
+    70, 71, 72, 73, 74, 75, 86, 87, 88
+];
+
+impl BlockKind {
+    /// Math block, evaluates an expression over its inputs.
+    pub const MATH_BLOCK: BlockKind = BlockKind(129);
+
+    /// Custom block types, see [`BlockKind::is_custom`].
+    pub const CUSTOM_BLOCKS: [BlockKind; 3] = [BlockKind(109), BlockKind(120), BlockKind(121)];
+
+    /// Returns every block type the catalog records something about, in id order.
+    pub fn known() -> impl Iterator<Item = BlockKind> {
+        (0..=u8::MAX).map(BlockKind).filter(|kind| kind.is_known())
+    }
+
+    /// Returns `true` if the catalog records something about the block type
+    /// beyond the defaults for unknown ids.
+    pub fn is_known(self) -> bool {
+        self == BlockKind::MATH_BLOCK || self.is_custom() || !self.is_interactable()
+    }
+
+    /// Returns the display name of the block type, if known.
+    pub fn name(self) -> Option<&'static str> {
+        match self {
+            BlockKind::MATH_BLOCK => Some("Math Block"),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the block type can be interacted with (it has an enable
+    /// state that can be driven by the player or by connections).
+    pub fn is_interactable(self) -> bool {
+        !NOT_INTERACTABLE.contains(&self.0)
+    }
+
+    /// Returns `true` for custom blocks, block types with user-defined behaviour.
+    pub fn is_custom(self) -> bool {
+        BlockKind::CUSTOM_BLOCKS.contains(&self)
+    }
+
+    /// Returns whether the block type mechanically attaches a block of another
+    /// root through [`Block::load`](crate::structs::Block::load), if known.
+    pub fn accepts_load(self) -> Option<bool> {
+        match self {
+            BlockKind::MATH_BLOCK => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Returns which `TypeSettings` variant the block type uses.
+    pub fn type_settings_kind(self) -> TypeSettingsKind {
+        match self {
+            BlockKind::MATH_BLOCK => TypeSettingsKind::MathBlock,
+            kind if kind.is_custom() => TypeSettingsKind::Unknown,
+            _ => TypeSettingsKind::None,
+        }
+    }
+}
+
+impl From<u8> for BlockKind {
+    fn from(id: u8) -> Self {
+        BlockKind(id)
+    }
+}
+
+impl From<BlockKind> for u8 {
+    fn from(kind: BlockKind) -> Self {
+        kind.0
+    }
+}
+
+impl fmt::Display for BlockKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{name} ({})", self.0),
+            None => write!(f, "Block {}", self.0),
+        }
+    }
+}
+
+#[test]
+fn test_catalog() {
+    let known: Vec<u8> = BlockKind::known().map(u8::from).collect();
+    assert_eq!(known.len(), 27 + 3 + 1);
+    assert!(known.windows(2).all(|w| w[0] < w[1]));
+    assert!(known.contains(&0) && known.contains(&109) && known.contains(&129));
+
+    assert_eq!(BlockKind::MATH_BLOCK.to_string(), "Math Block (129)");
+    assert_eq!(BlockKind(120).to_string(), "Block 120");
+    assert_eq!(BlockKind(2).to_string(), "Block 2");
+    assert!(!BlockKind(2).is_known());
+    assert_eq!(BlockKind::MATH_BLOCK.accepts_load(), Some(false));
+    assert_eq!(BlockKind(121).type_settings_kind(), TypeSettingsKind::Unknown);
+}
@@ -0,0 +1,475 @@
+//! Semantic differences between two revisions of a building.
+//!
+//! [`Building::diff`] matches the blocks of two buildings and lists what was
+//! added, removed or changed, field by field. The [`Diff`] prints as one change
+//! per line:
+//!
+//! ```rust
+//! use sw_structure_io::diff::Matching;
+//! use sw_structure_io::structs::*;
+//!
+//! let mut old = Building::default();
+//! old.roots.push(Root::default());
+//! old.blocks.push(Block::default());
+//! old.blocks.push(Block { position: [1.0, 0.0, 0.0], ..Default::default() });
+//!
+//! let mut new = old.clone();
+//! new.blocks[0].position = [0.0, 1.0, 0.0];
+//! new.connect(0, 1);
+//!
+//! let diff = old.diff(&new, Matching::Index);
+//! assert_eq!(diff.to_string(), "block 0 moved by (0, 1, 0)\nblock 0 gained connection to 1\n");
+//! ```
+//!
+//! Floats are compared exactly. Connections are compared as sets, so reordering
+//! them isn't a change.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::catalog::BlockKind;
+use crate::structs::{Block, Building, Gradient, Metadata, Root, TypeSettings};
+
+/// How blocks of the old building are matched to blocks of the new one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Matching {
+    /// Blocks with the same index are the same block.
+    #[default]
+    Index,
+
+    /// Blocks are matched by id and position first, then by index among the
+    /// remaining blocks with the same id. Use this when blocks were inserted or
+    /// removed and indices shifted. A block that was both moved and re-indexed
+    /// shows up as removed and added.
+    Heuristic,
+}
+
+/// Differences between two buildings, see [`Building::diff`].
+///
+/// Blocks and roots are referred to by their index in the old building when
+/// removed, and in the new building otherwise. Values that are block indices
+/// (`from` and `to` of loads, math inputs and connections) follow the same rule:
+/// `from` and removed connections are old indices, `to` and added connections
+/// new indices.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diff {
+    /// For every block of the old building, the index of the matching block in the
+    /// new building.
+    pub matches: Vec<Option<usize>>,
+
+    pub roots: Vec<RootChange>,
+
+    pub blocks: Vec<BlockChange>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RootChange {
+    Added { index: u16, root: Root },
+    Removed { index: u16, root: Root },
+    Modified { index: u16, from: Root, to: Root },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlockChange {
+    Added { index: usize, block: Block },
+    Removed { index: usize, block: Block },
+    Modified { old: usize, new: usize, changes: Vec<FieldChange> },
+}
+
+/// A change of a single block field.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FieldChange {
+    Id { from: BlockKind, to: BlockKind },
+    Root { from: u16, to: u16 },
+    Position { from: [f32; 3], to: [f32; 3] },
+    Rotation { from: [f32; 3], to: [f32; 3] },
+    Name { from: String, to: String },
+    EnableState { from: f32, to: f32 },
+    EnableStateCurrent { from: f32, to: f32 },
+    ConnectionAdded { target: usize },
+    ConnectionRemoved { target: usize },
+    Load { from: Option<usize>, to: Option<usize> },
+    Color { from: Option<[u8; 4]>, to: Option<[u8; 4]> },
+
+    /// Metadata was added or removed. Changes within metadata are reported per
+    /// field below.
+    Metadata { from: Option<Box<Metadata>>, to: Option<Box<Metadata>> },
+    Toggles { from: Vec<bool>, to: Vec<bool> },
+    Values { from: Vec<f32>, to: Vec<f32> },
+    Fields { from: Vec<Vec<i32>>, to: Vec<Vec<i32>> },
+    Dropdowns { from: Vec<i32>, to: Vec<i32> },
+    Colors { from: Vec<[f32; 4]>, to: Vec<[f32; 4]> },
+    Gradients { from: Vec<Gradient>, to: Vec<Gradient> },
+    Vectors { from: Vec<[f32; 3]>, to: Vec<[f32; 3]> },
+    MathFunction { from: String, to: String },
+
+    /// Math block inputs as `(source block, slot)` pairs.
+    MathInputs { from: Vec<(usize, u8)>, to: Vec<(usize, u8)> },
+
+    /// Type settings changed variant, or raw settings changed.
+    TypeSettings { from: TypeSettings, to: TypeSettings },
+}
+
+impl Diff {
+    /// Returns `true` if the buildings are the same.
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty() && self.blocks.is_empty()
+    }
+}
+
+impl Building {
+    /// Returns the differences from `self` to `new`.
+    pub fn diff(&self, new: &Building, matching: Matching) -> Diff {
+        let matches = match_blocks(self, new, matching);
+
+        let mut roots = Vec::new();
+        for index in 0..self.roots.len().max(new.roots.len()) {
+            let root_index = index as u16;
+            match (self.roots.get(index), new.roots.get(index)) {
+                (Some(from), Some(to)) if from != to => {
+                    roots.push(RootChange::Modified { index: root_index, from: from.clone(), to: to.clone() });
+                }
+                (Some(root), None) => roots.push(RootChange::Removed { index: root_index, root: root.clone() }),
+                (None, Some(root)) => roots.push(RootChange::Added { index: root_index, root: root.clone() }),
+                _ => {}
+            }
+        }
+
+        let mut blocks = Vec::new();
+        let mut matched = vec![false; new.blocks.len()];
+        for (old, m) in matches.iter().enumerate() {
+            match *m {
+                Some(new_index) => {
+                    matched[new_index] = true;
+                    let changes = block_changes(&self.blocks[old], &new.blocks[new_index], &matches);
+                    if !changes.is_empty() {
+                        blocks.push(BlockChange::Modified { old, new: new_index, changes });
+                    }
+                }
+                None => blocks.push(BlockChange::Removed { index: old, block: self.blocks[old].clone() }),
+            }
+        }
+        for (index, block) in new.blocks.iter().enumerate().filter(|&(i, _)| !matched[i]) {
+            blocks.push(BlockChange::Added { index, block: block.clone() });
+        }
+
+        Diff { matches, roots, blocks }
+    }
+}
+
+fn match_blocks(old: &Building, new: &Building, matching: Matching) -> Vec<Option<usize>> {
+    if matching == Matching::Index {
+        return (0..old.blocks.len()).map(|i| (i < new.blocks.len()).then_some(i)).collect();
+    }
+
+    let key = |block: &Block| (block.id, block.position.map(f32::to_bits));
+    let mut candidates: HashMap<_, Vec<usize>> = HashMap::new();
+    for (index, block) in new.blocks.iter().enumerate() {
+        candidates.entry(key(block)).or_default().push(index);
+    }
+
+    // Same id and position, preferring the same index.
+    let mut matches = vec![None; old.blocks.len()];
+    let mut taken = vec![false; new.blocks.len()];
+    for (index, block) in old.blocks.iter().enumerate() {
+        let Some(candidates) = candidates.get_mut(&key(block)) else {
+            continue;
+        };
+        if candidates.is_empty() {
+            continue;
+        }
+        let pick = candidates.iter().position(|&c| c == index).unwrap_or(0);
+        let found = candidates.remove(pick);
+        matches[index] = Some(found);
+        taken[found] = true;
+    }
+
+    // Same id and index among the rest.
+    for (index, block) in old.blocks.iter().enumerate() {
+        if matches[index].is_none()
+            && let Some(candidate) = new.blocks.get(index)
+            && !taken[index]
+            && candidate.id == block.id
+        {
+            matches[index] = Some(index);
+            taken[index] = true;
+        }
+    }
+
+    matches
+}
+
+fn math_inputs(metadata: &Metadata) -> Option<(&str, Vec<(usize, u8)>)> {
+    match &metadata.type_settings {
+        TypeSettings::MathBlock { function, incoming_connections_order, slots } => Some((
+            function,
+            incoming_connections_order.iter().map(|&s| s as usize).zip(slots.iter().copied()).collect(),
+        )),
+        _ => None,
+    }
+}
+
+fn block_changes(old: &Block, new: &Block, matches: &[Option<usize>]) -> Vec<FieldChange> {
+    let rebase = |index: usize| matches.get(index).copied().flatten();
+    let mut changes = Vec::new();
+
+    if old.id != new.id {
+        changes.push(FieldChange::Id { from: old.id, to: new.id });
+    }
+    if old.root != new.root {
+        changes.push(FieldChange::Root { from: old.root, to: new.root });
+    }
+    if old.position != new.position {
+        changes.push(FieldChange::Position { from: old.position, to: new.position });
+    }
+    if old.rotation != new.rotation {
+        changes.push(FieldChange::Rotation { from: old.rotation, to: new.rotation });
+    }
+    if old.name != new.name {
+        changes.push(FieldChange::Name { from: old.name.clone(), to: new.name.clone() });
+    }
+    if old.enable_state != new.enable_state {
+        changes.push(FieldChange::EnableState { from: old.enable_state, to: new.enable_state });
+    }
+    if old.enable_state_current != new.enable_state_current {
+        changes.push(FieldChange::EnableStateCurrent {
+            from: old.enable_state_current,
+            to: new.enable_state_current,
+        });
+    }
+
+    let rebased: Vec<Option<usize>> = old.connections.iter().map(|&c| rebase(c as usize)).collect();
+    for (&target, rebased) in old.connections.iter().zip(rebased.iter()) {
+        if !rebased.is_some_and(|r| new.connections.contains(&(r as u16))) {
+            changes.push(FieldChange::ConnectionRemoved { target: target as usize });
+        }
+    }
+    for &target in new.connections.iter() {
+        if !rebased.contains(&Some(target as usize)) {
+            changes.push(FieldChange::ConnectionAdded { target: target as usize });
+        }
+    }
+
+    let (load_from, load_to) = (old.load.map(usize::from), new.load.map(usize::from));
+    if load_from.and_then(rebase) != load_to || load_from.is_some() != load_to.is_some() {
+        changes.push(FieldChange::Load { from: load_from, to: load_to });
+    }
+    if old.color != new.color {
+        changes.push(FieldChange::Color { from: old.color, to: new.color });
+    }
+
+    match (&old.metadata, &new.metadata) {
+        (Some(from), Some(to)) => metadata_changes(from, to, &rebase, &mut changes),
+        (from, to) if from != to => changes.push(FieldChange::Metadata {
+            from: from.clone().map(Box::new),
+            to: to.clone().map(Box::new),
+        }),
+        _ => {}
+    }
+
+    changes
+}
+
+fn metadata_changes(
+    old: &Metadata,
+    new: &Metadata,
+    rebase: &impl Fn(usize) -> Option<usize>,
+    changes: &mut Vec<FieldChange>,
+) {
+    macro_rules! compare {
+        ($($field:ident => $variant:ident),*) => {$(
+            if old.$field != new.$field {
+                changes.push(FieldChange::$variant { from: old.$field.clone(), to: new.$field.clone() });
+            }
+        )*};
+    }
+    compare!(
+        toggles => Toggles,
+        values => Values,
+        fields => Fields,
+        dropdowns => Dropdowns,
+        colors => Colors,
+        gradients => Gradients,
+        vectors => Vectors
+    );
+
+    match (math_inputs(old), math_inputs(new)) {
+        (Some((old_function, old_inputs)), Some((new_function, new_inputs))) => {
+            if old_function != new_function {
+                changes.push(FieldChange::MathFunction { from: old_function.to_string(), to: new_function.to_string() });
+            }
+            let rebased: Vec<_> = old_inputs.iter().map(|&(source, slot)| (rebase(source), slot)).collect();
+            let expected: Vec<_> = new_inputs.iter().map(|&(source, slot)| (Some(source), slot)).collect();
+            if rebased != expected {
+                changes.push(FieldChange::MathInputs { from: old_inputs, to: new_inputs });
+            }
+        }
+        _ if old.type_settings != new.type_settings => changes.push(FieldChange::TypeSettings {
+            from: old.type_settings.clone(),
+            to: new.type_settings.clone(),
+        }),
+        _ => {}
+    }
+}
+
+struct Vector([f32; 3]);
+
+impl fmt::Display for Vector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({}, {}, {})", self.0[0], self.0[1], self.0[2])
+    }
+}
+
+struct Index(Option<usize>);
+
+impl fmt::Display for Index {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(index) => write!(f, "{index}"),
+            None => f.write_str("nothing"),
+        }
+    }
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldChange::Id { from, to } => write!(f, "changed from {from} to {to}"),
+            FieldChange::Root { from, to } => write!(f, "moved from root {from} to root {to}"),
+            FieldChange::Position { from, to } => {
+                write!(f, "moved by {}", Vector(std::array::from_fn(|i| to[i] - from[i])))
+            }
+            FieldChange::Rotation { from, to } => write!(f, "rotated from {} to {}", Vector(*from), Vector(*to)),
+            FieldChange::Name { from, to } => write!(f, "renamed from {from:?} to {to:?}"),
+            FieldChange::EnableState { from, to } => write!(f, "enable state changed from {from} to {to}"),
+            FieldChange::EnableStateCurrent { from, to } => {
+                write!(f, "current enable state changed from {from} to {to}")
+            }
+            FieldChange::ConnectionAdded { target } => write!(f, "gained connection to {target}"),
+            FieldChange::ConnectionRemoved { target } => write!(f, "lost connection to {target}"),
+            FieldChange::Load { from, to } => write!(f, "load changed from {} to {}", Index(*from), Index(*to)),
+            FieldChange::Color { from, to } => write!(f, "color changed from {from:?} to {to:?}"),
+            FieldChange::Metadata { from: None, .. } => f.write_str("metadata added"),
+            FieldChange::Metadata { to: None, .. } => f.write_str("metadata removed"),
+            FieldChange::Metadata { .. } => f.write_str("metadata changed"),
+            FieldChange::Toggles { from, to } => write!(f, "toggles changed from {from:?} to {to:?}"),
+            FieldChange::Values { from, to } => write!(f, "values changed from {from:?} to {to:?}"),
+            FieldChange::Fields { from, to } => write!(f, "fields changed from {from:?} to {to:?}"),
+            FieldChange::Dropdowns { from, to } => write!(f, "dropdowns changed from {from:?} to {to:?}"),
+            FieldChange::Colors { from, to } => write!(f, "colors changed from {from:?} to {to:?}"),
+            FieldChange::Gradients { .. } => f.write_str("gradients changed"),
+            FieldChange::Vectors { from, to } => write!(f, "vectors changed from {from:?} to {to:?}"),
+            FieldChange::MathFunction { from, to } => write!(f, "function changed from {from:?} to {to:?}"),
+            FieldChange::MathInputs { from, to } => write!(f, "math inputs changed from {from:?} to {to:?}"),
+            FieldChange::TypeSettings { from, to } => write!(f, "type settings changed from {from:?} to {to:?}"),
+        }
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in self.roots.iter() {
+            match change {
+                RootChange::Added { index, .. } => writeln!(f, "root {index} added")?,
+                RootChange::Removed { index, .. } => writeln!(f, "root {index} removed")?,
+                RootChange::Modified { index, from, to } => {
+                    if from.position != to.position {
+                        let offset = std::array::from_fn(|i| to.position[i] - from.position[i]);
+                        writeln!(f, "root {index} moved by {}", Vector(offset))?;
+                    }
+                    if from.rotation != to.rotation {
+                        writeln!(f, "root {index} rotated from {} to {}", Vector(from.rotation), Vector(to.rotation))?;
+                    }
+                }
+            }
+        }
+
+        for change in self.blocks.iter() {
+            match change {
+                BlockChange::Added { index, block } => writeln!(f, "block {index} added ({})", block.id)?,
+                BlockChange::Removed { index, block } => writeln!(f, "block {index} removed ({})", block.id)?,
+                BlockChange::Modified { old, new, changes } => {
+                    for change in changes {
+                        if old == new {
+                            writeln!(f, "block {new} {change}")?;
+                        } else {
+                            writeln!(f, "block {new} (was {old}) {change}")?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_diff() {
+    let mut old = Building::default();
+    old.roots.push(Root::default());
+    for x in 0..4 {
+        old.blocks.push(Block { position: [x as f32, 0.0, 0.0], ..Default::default() });
+    }
+    old.blocks.push(Block {
+        id: BlockKind::MATH_BLOCK,
+        position: [0.0, 1.0, 0.0],
+        metadata: Some(Metadata {
+            type_settings: TypeSettings::MathBlock {
+                function: "a".to_string(),
+                incoming_connections_order: vec![3],
+                slots: vec![0],
+            },
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    old.connect(3, 4);
+    old.connect(2, 1);
+
+    assert!(old.diff(&old, Matching::Index).is_empty());
+
+    // Remove a block so indices shift, then rewire.
+    let mut new = old.clone();
+    new.remove_block(0);
+    new.disconnect(2, 3);
+    new.connect(2, 0);
+    if let Some(metadata) = &mut new.blocks[3].metadata
+        && let TypeSettings::MathBlock { function, .. } = &mut metadata.type_settings
+    {
+        *function = "a * 2".to_string();
+    }
+    new.roots.push(Root::default());
+
+    let diff = old.diff(&new, Matching::Heuristic);
+    assert_eq!(diff.matches, vec![None, Some(0), Some(1), Some(2), Some(3)]);
+    assert_eq!(diff.roots, vec![RootChange::Added { index: 1, root: Root::default() }]);
+    assert_eq!(
+        diff.to_string(),
+        "root 1 added\n\
+         block 0 removed (Block 0)\n\
+         block 2 (was 3) lost connection to 4\n\
+         block 2 (was 3) gained connection to 0\n\
+         block 3 (was 4) function changed from \"a\" to \"a * 2\"\n\
+         block 3 (was 4) math inputs changed from [(3, 0)] to []\n"
+    );
+
+    new.blocks[0].position[1] = 1.0;
+    // By index, every block after the removed one looks changed.
+    let diff = old.diff(&new, Matching::Index);
+    assert_eq!(diff.matches, vec![Some(0), Some(1), Some(2), Some(3), None]);
+    assert!(matches!(
+        &diff.blocks[0],
+        BlockChange::Modified { changes, .. } if changes[0] == FieldChange::Position { from: [0.0; 3], to: [1.0, 1.0, 0.0] }
+    ));
+
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_string(&diff).unwrap();
+        assert_eq!(serde_json::from_str::<Diff>(&json).unwrap(), diff);
+    }
+}
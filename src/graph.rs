@@ -0,0 +1,247 @@
+//! Editing the connection graph of a building.
+//!
+//! Blocks refer to each other by index, through [`Block::connections`],
+//! [`Block::load`] and the `incoming_connections_order` of math blocks. Removing
+//! or inserting a block in [`Building::blocks`] shifts the indices of all blocks
+//! after it, so the methods here update every such reference along the way.
+
+use thiserror::Error;
+
+use crate::structs::{Block, Building, TypeSettings};
+
+/// A re-based index no longer fits the integer type it is stored in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+pub enum IndexOverflow {
+    #[error("block index {0} doesn't fit into a u16")]
+    Block(usize),
+
+    #[error("math block input {0} doesn't fit into a u8")]
+    MathInput(usize),
+
+    #[error("root index {0} doesn't fit into a u16")]
+    Root(usize),
+}
+
+impl Block {
+    /// Rewrites every block index this block refers to.
+    ///
+    /// `map` returns the new index, or `None` to drop the reference. Math block
+    /// slots are dropped together with their incoming connection. If a new index
+    /// doesn't fit, the block is left unchanged.
+    pub(crate) fn remap_references(&mut self, map: impl Fn(usize) -> Option<usize>) -> Result<(), IndexOverflow> {
+        let connections = self.connections
+            .iter()
+            .filter_map(|&c| map(c as usize))
+            .map(block_u16)
+            .collect::<Result<Vec<u16>, _>>()?;
+
+        let load = self.load.and_then(|l| map(l as usize)).map(block_u16).transpose()?;
+
+        let math_inputs = match &self.metadata {
+            Some(metadata) => match &metadata.type_settings {
+                TypeSettings::MathBlock { incoming_connections_order, slots, .. } => {
+                    let mut kept_slots = Vec::with_capacity(slots.len());
+                    let mut kept_order = Vec::with_capacity(incoming_connections_order.len());
+                    for (i, &source) in incoming_connections_order.iter().enumerate() {
+                        if let Some(source) = map(source as usize) {
+                            kept_order.push(u8::try_from(source).map_err(|_| IndexOverflow::MathInput(source))?);
+                            if let Some(&slot) = slots.get(i) {
+                                kept_slots.push(slot);
+                            }
+                        }
+                    }
+                    // Slots without a matching connection are kept as they are.
+                    kept_slots.extend(slots.iter().skip(incoming_connections_order.len()));
+                    Some((kept_order, kept_slots))
+                }
+                _ => None,
+            },
+            None => None,
+        };
+
+        self.connections = connections;
+        self.load = load;
+        if let Some((kept_order, kept_slots)) = math_inputs
+            && let Some(metadata) = &mut self.metadata
+            && let TypeSettings::MathBlock { incoming_connections_order, slots, .. } = &mut metadata.type_settings
+        {
+            *incoming_connections_order = kept_order;
+            *slots = kept_slots;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn block_u16(index: usize) -> Result<u16, IndexOverflow> {
+    u16::try_from(index).map_err(|_| IndexOverflow::Block(index))
+}
+
+impl Building {
+    /// Removes the block at `index` and returns it.
+    ///
+    /// Connections, loads and math block inputs that point to the removed block
+    /// are dropped, references to later blocks are shifted down. The returned
+    /// block is left unchanged.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of bounds.
+    pub fn remove_block(&mut self, index: usize) -> Block {
+        let removed = self.blocks.remove(index);
+        for block in self.blocks.iter_mut() {
+            block.remap_references(|i| match i {
+                i if i < index => Some(i),
+                i if i == index => None,
+                i => Some(i - 1),
+            }).expect("references only move to lower indices");
+        }
+        removed
+    }
+
+    /// Inserts a block at `index`, shifting all blocks after it.
+    ///
+    /// References of existing blocks are shifted up. The references of `block`
+    /// itself are taken as indices after the insertion and are left unchanged.
+    ///
+    /// # Errors
+    /// Returns [`IndexOverflow`] if a shifted reference no longer fits its integer
+    /// type (`u16` for blocks, `u8` for math block inputs). The building is left
+    /// unchanged in that case.
+    ///
+    /// # Panics
+    /// Panics if `index > blocks.len()`.
+    pub fn insert_block(&mut self, index: usize, block: Block) -> Result<(), IndexOverflow> {
+        assert!(index <= self.blocks.len(), "insertion index {index} is out of bounds");
+
+        // Only references at the top of their range can overflow, so look for them
+        // before anything is shifted.
+        for existing in self.blocks.iter() {
+            let mut blocks = existing.connections.iter().chain(existing.load.iter());
+            if blocks.any(|&i| i == u16::MAX && i as usize >= index) {
+                return Err(IndexOverflow::Block(u16::MAX as usize + 1));
+            }
+            if let Some(metadata) = &existing.metadata
+                && let TypeSettings::MathBlock { incoming_connections_order, .. } = &metadata.type_settings
+                && incoming_connections_order.iter().any(|&i| i == u8::MAX && i as usize >= index)
+            {
+                return Err(IndexOverflow::MathInput(u8::MAX as usize + 1));
+            }
+        }
+
+        for existing in self.blocks.iter_mut() {
+            existing.remap_references(|i| Some(if i < index { i } else { i + 1 }))?;
+        }
+        self.blocks.insert(index, block);
+        Ok(())
+    }
+
+    /// Connects the block `from` to the block `to`.
+    ///
+    /// Returns `false` if the connection already existed. If `to` is a math
+    /// block, the new input still has to be assigned to a slot.
+    ///
+    /// # Panics
+    /// Panics if either block doesn't exist, or if `to` doesn't fit into a `u16`.
+    pub fn connect(&mut self, from: usize, to: usize) -> bool {
+        assert!(to < self.blocks.len(), "block {to} doesn't exist");
+        let to = u16::try_from(to).expect("block index exceeds u16::MAX");
+        let connections = &mut self.blocks[from].connections;
+        if connections.contains(&to) {
+            return false;
+        }
+        connections.push(to);
+        true
+    }
+
+    /// Removes the connection from block `from` to block `to`, along with the
+    /// math block input of `to` it fed.
+    ///
+    /// Returns `false` if there was no such connection.
+    ///
+    /// # Panics
+    /// Panics if either block doesn't exist.
+    pub fn disconnect(&mut self, from: usize, to: usize) -> bool {
+        assert!(to < self.blocks.len(), "block {to} doesn't exist");
+        let connections = &mut self.blocks[from].connections;
+        let before = connections.len();
+        connections.retain(|&c| c as usize != to);
+        if connections.len() == before {
+            return false;
+        }
+
+        if let Some(metadata) = &mut self.blocks[to].metadata
+            && let TypeSettings::MathBlock { incoming_connections_order, slots, .. } = &mut metadata.type_settings
+            && let Some(i) = incoming_connections_order.iter().position(|&s| s as usize == from)
+        {
+            incoming_connections_order.remove(i);
+            if i < slots.len() {
+                slots.remove(i);
+            }
+        }
+        true
+    }
+
+    /// Returns the blocks `index` is connected to.
+    ///
+    /// # Panics
+    /// Panics if the block doesn't exist.
+    pub fn outgoing(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        self.blocks[index].connections.iter().map(|&c| c as usize)
+    }
+
+    /// Returns the blocks connected to `index`, in ascending order.
+    pub fn incoming(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        self.blocks
+            .iter()
+            .enumerate()
+            .filter(move |(_, b)| b.connections.iter().any(|&c| c as usize == index))
+            .map(|(i, _)| i)
+    }
+}
+
+#[test]
+fn test_graph() {
+    use crate::catalog::BlockKind;
+    use crate::structs::*;
+
+    let math = |order: Vec<u8>, slots: Vec<u8>| Some(Metadata {
+        type_settings: TypeSettings::MathBlock { function: "a + b".to_string(), incoming_connections_order: order, slots },
+        ..Default::default()
+    });
+
+    let mut building = Building::default();
+    building.roots.push(Root::default());
+    building.roots.push(Root::default());
+    building.blocks.push(Block { connections: vec![3], ..Default::default() });
+    building.blocks.push(Block { connections: vec![3], load: Some(2), ..Default::default() });
+    building.blocks.push(Block { root: 1, load: Some(0), ..Default::default() });
+    building.blocks.push(Block { id: BlockKind::MATH_BLOCK, metadata: math(vec![0, 1], vec![0, 1]), ..Default::default() });
+
+    assert_eq!(building.incoming(3).collect::<Vec<_>>(), vec![0, 1]);
+
+    let removed = building.remove_block(0);
+    assert_eq!(removed.connections, vec![3]);
+    assert_eq!(building.blocks[0].connections, vec![2]);
+    assert_eq!(building.blocks[1].load, None);
+    assert_eq!(building.blocks[2].metadata, math(vec![0], vec![1]));
+    assert!(building.validate().is_empty());
+
+    building.insert_block(0, Block::default()).unwrap();
+    assert_eq!(building.blocks[1].connections, vec![3]);
+    assert_eq!(building.blocks[1].load, Some(2));
+    assert_eq!(building.blocks[3].metadata, math(vec![1], vec![1]));
+
+    assert!(building.connect(0, 3));
+    assert!(!building.connect(0, 3));
+    assert_eq!(building.outgoing(0).collect::<Vec<_>>(), vec![3]);
+
+    assert!(building.disconnect(1, 3));
+    assert!(!building.disconnect(1, 3));
+    assert_eq!(building.blocks[3].metadata, math(vec![], vec![]));
+    assert_eq!(building.incoming(3).collect::<Vec<_>>(), vec![0]);
+
+    // A math input at the top of its range can't be shifted.
+    building.blocks[3].metadata = math(vec![255], vec![0]);
+    let before = building.clone();
+    assert_eq!(building.insert_block(0, Block::default()), Err(IndexOverflow::MathInput(256)));
+    assert_eq!(building, before);
+}
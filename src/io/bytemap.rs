@@ -0,0 +1,158 @@
+use std::fmt;
+
+use crate::io::utils::Tracked;
+use crate::io::{read_tracked, Error, FieldPath, ReadOptions};
+
+/// A byte range of a structure file and the field it belongs to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ByteRange {
+    /// Offset of the first byte.
+    pub start: u64,
+
+    /// Offset after the last byte.
+    pub end: u64,
+
+    /// The innermost field the bytes were read for. Length prefixes belong to the
+    /// list they precede, e.g. `blocks` for the block count.
+    pub path: FieldPath,
+
+    /// Meaning and value of every bit, lowest first, for flag bytes.
+    pub bits: Vec<(&'static str, bool)>,
+}
+
+/// Which field every byte of a structure file belongs to, see [`byte_map`].
+///
+/// Printing it gives an annotated hex dump with one range per line.
+#[derive(Debug)]
+pub struct ByteMap {
+    /// The whole file.
+    pub data: Vec<u8>,
+
+    /// Consecutive ranges from offset 0 to where parsing stopped.
+    pub ranges: Vec<ByteRange>,
+
+    /// Why parsing stopped before the end of the data.
+    pub error: Option<Error>,
+}
+
+impl ByteMap {
+    /// Returns the range containing the byte at `offset`.
+    pub fn range_at(&self, offset: u64) -> Option<&ByteRange> {
+        let i = self.ranges.partition_point(|r| r.end <= offset);
+        self.ranges.get(i).filter(|r| r.start <= offset)
+    }
+
+    /// Offset of the first byte that wasn't parsed, the length of the data if
+    /// everything was.
+    pub fn parsed_len(&self) -> u64 {
+        self.ranges.last().map_or(0, |r| r.end)
+    }
+}
+
+/// Parses a structure file and records the field every byte belongs to.
+///
+/// Parsing stops at the first error, which is kept in [`ByteMap::error`]. The
+/// ranges before it are still mapped, so files of versions that don't parse
+/// completely can be compared field by field.
+///
+/// ```rust
+/// use sw_structure_io::io::{byte_map, WriteBuilding};
+/// use sw_structure_io::structs::*;
+///
+/// let mut building = Building::default();
+/// building.roots.push(Root::default());
+/// building.blocks.push(Block { name: "Lamp".to_string(), ..Default::default() });
+///
+/// let mut data = Vec::new();
+/// data.write_building(&building, 0).unwrap();
+///
+/// let map = byte_map(&data);
+/// assert_eq!(map.range_at(0).unwrap().path.to_string(), "version");
+/// assert!(map.ranges.iter().any(|r| r.path.to_string() == "blocks[0].name"));
+/// ```
+pub fn byte_map(data: &[u8]) -> ByteMap {
+    let mut r = Tracked::recording(data);
+    let error = read_tracked(&mut r, &ReadOptions { trailing: true }).err();
+
+    ByteMap {
+        data: data.to_vec(),
+        ranges: r.take_ranges(),
+        error,
+    }
+}
+
+/// Bytes shown per line before the rest is elided.
+const HEX_WIDTH: usize = 8;
+
+impl fmt::Display for ByteMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for range in self.ranges.iter() {
+            let bytes = &self.data[range.start as usize..range.end as usize];
+            let mut hex: Vec<String> = bytes.iter().take(HEX_WIDTH).map(|b| format!("{b:02x}")).collect();
+            if bytes.len() > HEX_WIDTH {
+                hex.push(format!("+{}", bytes.len() - HEX_WIDTH));
+            }
+
+            write!(f, "{:08x}  {:<width$}  {}", range.start, hex.join(" "), range.path, width = HEX_WIDTH * 3 + 3)?;
+            for (name, set) in range.bits.iter() {
+                write!(f, " {name}={}", *set as u8)?;
+            }
+            writeln!(f)?;
+        }
+
+        let parsed = self.parsed_len();
+        if parsed < self.data.len() as u64 {
+            writeln!(f, "{parsed:08x}  {} bytes not parsed", self.data.len() as u64 - parsed)?;
+        }
+        if let Some(error) = &self.error {
+            writeln!(f, "error: {error}")?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_byte_map() {
+    use crate::io::WriteBuilding;
+    use crate::structs::*;
+
+    let mut building = Building::default();
+    building.roots.push(Root::default());
+    building.blocks.push(Block { name: "Lamp".to_string(), load: Some(1), ..Default::default() });
+    building.blocks.push(Block {
+        metadata: Some(Metadata { toggles: vec![true], ..Default::default() }),
+        connections: vec![0],
+        ..Default::default()
+    });
+    building.trailing = vec![1, 2, 3];
+
+    for version in [0, 6] {
+        let mut data = Vec::new();
+        data.write_building(&building, version).unwrap();
+        let map = byte_map(&data);
+
+        assert!(map.error.is_none());
+        assert_eq!(map.parsed_len(), data.len() as u64);
+        assert!(map.ranges.windows(2).all(|w| w[0].end == w[1].start && w[0].start < w[0].end));
+        assert_eq!(map.range_at(data.len() as u64 - 1).unwrap().path.to_string(), "trailing");
+
+        let path = |path: &str| map.ranges.iter().find(|r| r.path.to_string() == path).unwrap();
+        let flags = &path("blocks[0].flags").bits;
+        assert_eq!(flags.len(), 8);
+        assert!(flags.contains(&("has_name", true)) && flags.contains(&("no_load", false)));
+        assert_eq!(path("blocks[0].name").end - path("blocks[0].name").start, 5);
+        assert!(path("blocks[1].metadata.toggles").end > path("blocks[1].metadata.toggles").start);
+    }
+
+    // Parsing stops at the error, the ranges before it are kept.
+    let mut data = Vec::new();
+    data.write_building(&building, 0).unwrap();
+    let map = byte_map(&data[..20]);
+    assert!(map.error.is_some());
+    assert_eq!(map.ranges[0].path.to_string(), "version");
+    assert!(map.to_string().contains("error: "));
+
+    let map = byte_map(&[3, 1, 2]);
+    assert_eq!(map.parsed_len(), 1);
+    assert!(map.to_string().contains("2 bytes not parsed"));
+}
@@ -0,0 +1,492 @@
+use std::fmt;
+
+use crate::io::{version, ErrorKind, ReadBuilding, ReadOptions, Result, WriteBuilding};
+use crate::structs::{Block, Building, Metadata, TypeSettings};
+
+/// A building converted to another format version, see [`convert`].
+#[derive(Clone, Debug)]
+pub struct Conversion {
+    /// The version the building was read from.
+    pub from: u8,
+
+    /// The target version.
+    pub to: u8,
+
+    /// The building exactly as it reads back after being written in `to`.
+    pub building: Building,
+
+    /// Everything that changed on the way.
+    pub losses: Vec<Loss>,
+}
+
+impl Conversion {
+    /// Returns `true` if writing in the target version keeps everything.
+    pub fn is_lossless(&self) -> bool {
+        self.losses.is_empty()
+    }
+}
+
+/// A single piece of information that is lost or quantized by a conversion.
+///
+/// Block and root indices refer to the building as it reads back from the source
+/// version, which is the original building if it was read from there.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Loss {
+    /// The block is stored at a different index, e.g. because v6 groups blocks
+    /// by root. References to it are re-based, so nothing is lost, but indices
+    /// kept outside of the building become stale.
+    Reordered {
+        block: usize,
+        index: usize,
+    },
+
+    /// The position was quantized (v6 stores positions as `i16` within the
+    /// bounds of the root).
+    Position {
+        block: usize,
+        before: [f32; 3],
+        after: [f32; 3],
+    },
+
+    /// The rotation was quantized to `u16` steps.
+    Rotation {
+        block: usize,
+        before: [f32; 3],
+        after: [f32; 3],
+    },
+
+    /// The color was reduced (v6 stores colors as RGB565 without alpha).
+    Color {
+        block: usize,
+        before: [u8; 4],
+        after: [u8; 4],
+    },
+
+    /// `enable_state` was quantized to a byte.
+    EnableState {
+        block: usize,
+        before: f32,
+        after: f32,
+    },
+
+    /// `enable_state_current` was quantized to a byte.
+    EnableStateCurrent {
+        block: usize,
+        before: f32,
+        after: f32,
+    },
+
+    /// The root is stored at a different index, e.g. because v6 can't store an
+    /// empty first root. Blocks follow their root, so nothing is lost, but
+    /// indices kept outside of the building become stale.
+    RootReordered {
+        root: usize,
+        index: usize,
+    },
+
+    /// The block ended up on another root.
+    Root {
+        block: usize,
+        before: u16,
+        after: u16,
+    },
+
+    /// A math block input moved to a block index that doesn't fit into a `u8`.
+    /// The math block settings can't be stored and are left empty.
+    MathInput {
+        block: usize,
+        source: usize,
+    },
+
+    /// Any other field of the block changed.
+    Other {
+        block: usize,
+        field: &'static str,
+    },
+
+    /// The number of roots or blocks changed.
+    Count {
+        field: &'static str,
+        before: usize,
+        after: usize,
+    },
+}
+
+impl fmt::Display for Loss {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Loss::Reordered { block, index } => write!(f, "block {block} moves to index {index}"),
+            Loss::Position { block, before, after } => {
+                write!(f, "block {block} position {before:?} becomes {after:?}")
+            }
+            Loss::Rotation { block, before, after } => {
+                write!(f, "block {block} rotation {before:?} becomes {after:?}")
+            }
+            Loss::Color { block, before, after } => {
+                write!(f, "block {block} color {before:?} becomes {after:?}")
+            }
+            Loss::EnableState { block, before, after } => {
+                write!(f, "block {block} enable_state {before} becomes {after}")
+            }
+            Loss::EnableStateCurrent { block, before, after } => {
+                write!(f, "block {block} enable_state_current {before} becomes {after}")
+            }
+            Loss::RootReordered { root, index } => write!(f, "root {root} moves to index {index}"),
+            Loss::Root { block, before, after } => {
+                write!(f, "block {block} moves from root {before} to root {after}")
+            }
+            Loss::MathInput { block, source } => {
+                write!(f, "math block {block} input from block {source} can't be stored")
+            }
+            Loss::Other { block, field } => write!(f, "block {block} {field} changes"),
+            Loss::Count { field, before, after } => {
+                write!(f, "{field} count {before} becomes {after}")
+            }
+        }
+    }
+}
+
+/// Converts a building read from version `from` to version `to`.
+///
+/// The building is written in `to` and read back, so the returned building is
+/// exactly what a reader of the converted file sees. Every difference to the
+/// input is reported as a [`Loss`], so the caller can decide whether to write it.
+///
+/// Only information a file of version `from` can hold is compared: the building
+/// is first written and read back in `from`, so precision it never had there,
+/// e.g. color alpha for v6, isn't reported as lost.
+///
+/// Advanced settings this crate can't read back, those of custom blocks and math
+/// block settings that don't match their function, are written unchanged. They
+/// are carried over to the converted building as written instead.
+///
+/// # Errors
+/// Returns an error if either version is not supported, or if the building
+/// can't be written in `to` at all (e.g. more than 256 roots in v0).
+pub fn convert(building: &Building, from: u8, to: u8) -> Result<Conversion> {
+    for version in [from, to] {
+        if !matches!(version, 0 | 6) {
+            return Err(ErrorKind::UnsuportedVersion { version }.into());
+        }
+    }
+
+    let mut losses = Vec::new();
+
+    let from_order = block_order(building, from);
+    let (prepared, from_losses) = storable(building, &from_order);
+    // Blocks are reported at their index in the source building.
+    let source_index = inverse(&from_order);
+    losses.extend(from_losses.into_iter().map(|loss| match loss {
+        Loss::MathInput { block, source } => Loss::MathInput { block: source_index[block], source },
+        loss => loss,
+    }));
+    let source = round_trip(&prepared, from)?;
+
+    let order = block_order(&source, to);
+    let (prepared, to_losses) = storable(&source, &order);
+    losses.extend(to_losses);
+    let converted = round_trip(&prepared, to)?;
+
+    losses.extend(compare(&prepared, &converted, &order, &root_order(&prepared, to)));
+
+    Ok(Conversion {
+        from,
+        to,
+        building: converted,
+        losses,
+    })
+}
+
+/// Returns the indices of `building.blocks` in the order `version` stores them.
+fn block_order(building: &Building, version: u8) -> Vec<usize> {
+    match version {
+        6 => version::v6::block_order(building),
+        _ => (0..building.blocks.len()).collect(),
+    }
+}
+
+/// Returns the indices of `building.roots` in the order `version` stores them.
+fn root_order(building: &Building, version: u8) -> Vec<usize> {
+    match version {
+        6 => version::v6::root_order(building),
+        _ => (0..building.roots.len()).collect(),
+    }
+}
+
+/// Maps every index of `order` to its position in `order`.
+fn inverse(order: &[usize]) -> Vec<usize> {
+    let mut new_index = vec![0usize; order.len()];
+    for (new, &old) in order.iter().enumerate() {
+        new_index[old] = new;
+    }
+    new_index
+}
+
+/// Empties the math block settings with inputs that don't fit into a `u8` after
+/// writing the blocks in the given order, reporting each with the block index
+/// in `building`.
+fn storable(building: &Building, order: &[usize]) -> (Building, Vec<Loss>) {
+    let new_index = inverse(order);
+
+    let mut building = building.clone();
+    let mut losses = Vec::new();
+    for (block, b) in building.blocks.iter_mut().enumerate() {
+        let Some(metadata) = &mut b.metadata else {
+            continue;
+        };
+        let TypeSettings::MathBlock { incoming_connections_order, .. } = &metadata.type_settings else {
+            continue;
+        };
+        let source = incoming_connections_order
+            .iter()
+            .map(|&s| new_index.get(s as usize).map_or(s as usize, |&i| i))
+            .find(|&s| s > u8::MAX as usize);
+        if let Some(source) = source {
+            losses.push(Loss::MathInput { block, source });
+            metadata.type_settings = TypeSettings::MathBlock {
+                function: String::new(),
+                incoming_connections_order: Vec::new(),
+                slots: Vec::new(),
+            };
+        }
+    }
+
+    (building, losses)
+}
+
+/// Writes the building in `version` and reads it back.
+///
+/// The metadata of blocks whose advanced settings can't be read back is left
+/// out and put back on the blocks where they end up instead, with math block
+/// inputs following reordered blocks.
+fn round_trip(building: &Building, version: u8) -> Result<Building> {
+    let mut readable = building.clone();
+    let mut carried = Vec::new();
+    for (index, block) in readable.blocks.iter_mut().enumerate() {
+        if let Some(metadata) = &block.metadata
+            && !version::v0::type_settings_readable(&metadata.type_settings, block.id)
+        {
+            carried.push((index, block.metadata.take()));
+        }
+    }
+
+    let mut buffer = Vec::new();
+    buffer.write_building(&readable, version)?;
+    let mut read = (&buffer[..]).read_building_with(&ReadOptions { trailing: true })?;
+
+    let new_index = inverse(&block_order(building, version));
+    let rebase = |index: u16| new_index.get(index as usize).map_or(index as usize, |&i| i);
+    for (index, metadata) in carried {
+        read.blocks[new_index[index]].metadata = metadata.map(|metadata| rebase_math_inputs(&metadata, &rebase));
+    }
+
+    Ok(read)
+}
+
+/// Returns the metadata with math block inputs re-based to the new block indices.
+/// Inputs that don't fit into a `u8` are left out.
+fn rebase_math_inputs(metadata: &Metadata, rebase: &impl Fn(u16) -> usize) -> Metadata {
+    match &metadata.type_settings {
+        TypeSettings::MathBlock { function, incoming_connections_order, slots } => Metadata {
+            type_settings: TypeSettings::MathBlock {
+                function: function.clone(),
+                incoming_connections_order: incoming_connections_order
+                    .iter()
+                    .filter_map(|&i| u8::try_from(rebase(i as u16)).ok())
+                    .collect(),
+                slots: slots.clone(),
+            },
+            ..metadata.clone()
+        },
+        _ => metadata.clone(),
+    }
+}
+
+fn compare(before: &Building, after: &Building, order: &[usize], root_order: &[usize]) -> Vec<Loss> {
+    let mut losses = Vec::new();
+
+    // Root transforms are stored as plain floats in every version, only their
+    // order can change.
+    let new_root = inverse(root_order);
+    if before.roots.len() != after.roots.len() || root_order.len() != after.roots.len() {
+        losses.push(Loss::Count { field: "roots", before: before.roots.len(), after: after.roots.len() });
+    } else {
+        for (root, &index) in new_root.iter().enumerate() {
+            if index != root {
+                losses.push(Loss::RootReordered { root, index });
+            }
+        }
+    }
+    if before.blocks.len() != after.blocks.len() || order.len() != after.blocks.len() {
+        losses.push(Loss::Count { field: "blocks", before: before.blocks.len(), after: after.blocks.len() });
+        return losses;
+    }
+
+    // Maps an original block index to its index in `after`.
+    let new_index = inverse(order);
+    let rebase = |index: u16| new_index.get(index as usize).map_or(index as usize, |&i| i);
+
+    for (block, b) in before.blocks.iter().enumerate() {
+        let index = new_index[block];
+        let a = &after.blocks[index];
+
+        if index != block {
+            losses.push(Loss::Reordered { block, index });
+        }
+        if !same_array(&b.position, &a.position) {
+            losses.push(Loss::Position { block, before: b.position, after: a.position });
+        }
+        if !same_array(&b.rotation, &a.rotation) {
+            losses.push(Loss::Rotation { block, before: b.rotation, after: a.rotation });
+        }
+        if b.color != a.color {
+            match (b.color, a.color) {
+                (Some(before), Some(after)) => losses.push(Loss::Color { block, before, after }),
+                _ => losses.push(Loss::Other { block, field: "color" }),
+            }
+        }
+        if !same(b.enable_state, a.enable_state) {
+            losses.push(Loss::EnableState { block, before: b.enable_state, after: a.enable_state });
+        }
+        if !same(b.enable_state_current, a.enable_state_current) {
+            losses.push(Loss::EnableStateCurrent {
+                block,
+                before: b.enable_state_current,
+                after: a.enable_state_current,
+            });
+        }
+        if new_root.get(b.root as usize).map_or(b.root as usize, |&r| r) != a.root as usize {
+            losses.push(Loss::Root { block, before: b.root, after: a.root });
+        }
+        if b.id != a.id {
+            losses.push(Loss::Other { block, field: "id" });
+        }
+        if b.name != a.name {
+            losses.push(Loss::Other { block, field: "name" });
+        }
+        if b.load.map(rebase) != a.load.map(|l| l as usize) {
+            losses.push(Loss::Other { block, field: "load" });
+        }
+        if !b.connections.iter().map(|&c| rebase(c)).eq(a.connections.iter().map(|&c| c as usize)) {
+            losses.push(Loss::Other { block, field: "connections" });
+        }
+        if !same_metadata(b, a, &rebase) {
+            losses.push(Loss::Other { block, field: "metadata" });
+        }
+    }
+
+    losses
+}
+
+fn same(a: f32, b: f32) -> bool {
+    a.to_bits() == b.to_bits() || a == b
+}
+
+fn same_array<const N: usize>(a: &[f32; N], b: &[f32; N]) -> bool {
+    a.iter().zip(b.iter()).all(|(&a, &b)| same(a, b))
+}
+
+fn same_metadata(before: &Block, after: &Block, rebase: &impl Fn(u16) -> usize) -> bool {
+    let (Some(b), Some(a)) = (&before.metadata, &after.metadata) else {
+        return before.metadata.is_none() && after.metadata.is_none();
+    };
+
+    // Math block inputs are block indices, so they follow reordered blocks. Those
+    // that don't fit into a `u8` were dropped before writing.
+    &rebase_math_inputs(b, rebase) == a
+}
+
+#[test]
+fn test_convert() {
+    use crate::catalog::BlockKind;
+    use crate::structs::*;
+
+    let mut building = Building::default();
+    building.roots.push(Root::default());
+    building.roots.push(Root::default());
+    building.blocks.push(Block { root: 1, position: [0.1, 0.0, 0.0], color: Some([255, 255, 255, 128]), ..Default::default() });
+    building.blocks.push(Block {
+        position: [10.0, 0.0, 0.0],
+        connections: vec![0],
+        color: Some([17, 134, 70, 255]),
+        ..Default::default()
+    });
+
+    let conversion = convert(&building, 6, 0).unwrap();
+    assert!(conversion.is_lossless());
+
+    let conversion = convert(&building, 0, 6).unwrap();
+    assert!(conversion.losses.contains(&Loss::Reordered { block: 0, index: 1 }));
+    assert!(conversion.losses.contains(&Loss::Color {
+        block: 0,
+        before: [255, 255, 255, 128],
+        after: [248, 252, 248, 255],
+    }));
+    // Green and blue keep their own bits.
+    assert_eq!(conversion.building.blocks[0].color, Some([16, 132, 64, 255]));
+    // Connections follow the reordered blocks, so they are not reported.
+    assert!(!conversion.losses.iter().any(|l| matches!(l, Loss::Other { .. })));
+    assert_eq!(conversion.building.blocks[0].connections, vec![1]);
+
+    // v6 never held the alpha channel, so it isn't lost.
+    let conversion = convert(&building, 6, 6).unwrap();
+    assert!(!conversion.losses.iter().any(|l| matches!(l, Loss::Color { .. })));
+
+    assert!(convert(&building, 0, 3).is_err());
+
+    // Math inputs that don't fit after reordering are reported and dropped.
+    let mut building = Building::default();
+    building.roots.push(Root::default());
+    building.roots.push(Root::default());
+    building.blocks.resize_with(301, Block::default);
+    // v6 groups blocks by root, which moves block 200 to index 300.
+    building.blocks[200].root = 1;
+    building.blocks[0].id = BlockKind::MATH_BLOCK;
+    building.blocks[0].metadata = Some(Metadata {
+        type_settings: TypeSettings::MathBlock { function: "a".into(), incoming_connections_order: vec![200], slots: vec![0] },
+        ..Default::default()
+    });
+    // Settings this crate can't read back are carried over as written.
+    building.blocks[1].id = BlockKind(109);
+    building.blocks[1].metadata = Some(Metadata {
+        values: vec![1.0],
+        type_settings: TypeSettings::Raw(vec![1, 2]),
+        ..Default::default()
+    });
+    building.blocks[2].id = BlockKind::MATH_BLOCK;
+    building.blocks[2].metadata = Some(Metadata {
+        type_settings: TypeSettings::MathBlock { function: "a+b".into(), incoming_connections_order: vec![250], slots: vec![0] },
+        ..Default::default()
+    });
+
+    let conversion = convert(&building, 0, 6).unwrap();
+    assert_eq!(conversion.losses.iter().filter(|l| !matches!(l, Loss::Reordered { .. })).collect::<Vec<_>>(), vec![
+        &Loss::MathInput { block: 0, source: 300 },
+    ]);
+    let empty = TypeSettings::MathBlock { function: String::new(), incoming_connections_order: vec![], slots: vec![] };
+    assert_eq!(conversion.building.blocks[0].metadata.as_ref().unwrap().type_settings, empty);
+    assert_eq!(conversion.building.blocks[1].metadata, building.blocks[1].metadata);
+    assert_eq!(
+        conversion.building.blocks[2].metadata.as_ref().unwrap().type_settings,
+        TypeSettings::MathBlock { function: "a+b".into(), incoming_connections_order: vec![249], slots: vec![0] },
+    );
+
+    // The same inputs fit into v0, and nothing changes.
+    let conversion = convert(&building, 0, 0).unwrap();
+    assert!(conversion.is_lossless());
+    assert_eq!(conversion.building, building);
+
+    // An empty first root moves behind the others in v6, its blocks follow it.
+    let mut building = Building::default();
+    building.roots.push(Root::default());
+    building.roots.push(Root { position: [1.0, 0.0, 0.0], ..Default::default() });
+    building.blocks.push(Block { root: 1, ..Default::default() });
+
+    let conversion = convert(&building, 0, 6).unwrap();
+    assert_eq!(conversion.losses, vec![
+        Loss::RootReordered { root: 0, index: 1 },
+        Loss::RootReordered { root: 1, index: 0 },
+    ]);
+    assert_eq!(conversion.building.roots[0], building.roots[1]);
+    assert_eq!(conversion.building.blocks[0].root, 0);
+}
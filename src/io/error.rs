@@ -0,0 +1,183 @@
+use std::{fmt, string::FromUtf8Error};
+use thiserror::Error;
+
+use crate::validation::Diagnostic;
+
+/// Location of a field inside a serialized building.
+///
+/// Displayed as a Rust-like access path, e.g. `blocks[412].metadata.gradients[1].alpha_keys`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FieldPath(pub(crate) Vec<PathSegment>);
+
+/// A single step of a [`FieldPath`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathSegment {
+    /// A named field.
+    Field(&'static str),
+    /// An element of a list.
+    Index(usize),
+}
+
+impl FieldPath {
+    /// Returns the individual steps of the path.
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.0
+    }
+
+    /// Returns `true` if the path points at the start of the stream.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Display for FieldPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                PathSegment::Field(name) if i == 0 => write!(f, "{name}")?,
+                PathSegment::Field(name) => write!(f, ".{name}")?,
+                PathSegment::Index(index) => write!(f, "[{index}]")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Error returned by building serialization and deserialization.
+///
+/// Besides the [`ErrorKind`], every error records the field that was being read
+/// or written and the byte offset of that field from the start of the stream
+/// (the version byte is at offset 0).
+#[derive(Debug)]
+pub struct Error {
+    /// What went wrong.
+    pub kind: ErrorKind,
+
+    /// The field that was being read or written.
+    pub path: FieldPath,
+
+    /// Byte offset of the field in the stream.
+    pub offset: u64,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{} (at byte offset {})", self.kind, self.offset)
+        } else {
+            write!(f, "{} (at `{}`, byte offset {})", self.kind, self.path, self.offset)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.kind)
+    }
+}
+
+/// The reason an [`Error`](struct@Error) occurred.
+#[derive(Error, Debug)]
+pub enum ErrorKind {
+    #[error("Unexpected end of data.")]
+    Truncated,
+    #[error("Length {len} doesn't fit into the length field (max {max}).")]
+    LengthOverflow {
+        len: usize,
+        max: usize,
+    },
+    #[error("Value {value} doesn't fit into the field (max {max}).")]
+    ValueOverflow {
+        value: usize,
+        max: usize,
+    },
+    #[error("Name is not valid UTF-8: {0}")]
+    InvalidUtf8(#[from] FromUtf8Error),
+    #[error("Reference to index {index}, but only {len} are available.")]
+    BadReference {
+        index: usize,
+        len: usize,
+    },
+    #[error("The version {version:?} is not supported")]
+    UnsuportedVersion {
+        version: u8
+    },
+    #[error("The advanced settings of block type {id} can't be read, their length isn't known")]
+    UnreadableTypeSettings {
+        id: u8
+    },
+    #[error("The building is invalid: {}", .diagnostics.iter().map(|d| d.to_string()).collect::<Vec<_>>().join("; "))]
+    InvalidBuilding {
+        diagnostics: Vec<Diagnostic>,
+    },
+    #[error("I/O error: {0}")]
+    Io(std::io::Error),
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            path: FieldPath::default(),
+            offset: 0,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => ErrorKind::Truncated.into(),
+            _ => ErrorKind::Io(e).into(),
+        }
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(e: FromUtf8Error) -> Self {
+        ErrorKind::InvalidUtf8(e).into()
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[test]
+fn test_error_location() {
+    use crate::io::{ReadBuilding, WriteBuilding};
+    use crate::structs::*;
+
+    let mut building = Building::default();
+    building.roots.push(Root::default());
+    building.blocks.push(Block::default());
+    building.blocks.push(Block {
+        metadata: Some(Metadata {
+            gradients: vec![Gradient {
+                color_keys: vec![],
+                color_time_keys: vec![],
+                alpha_keys: vec![0.0; 0x10000],
+                alpha_time_keys: vec![],
+            }],
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+
+    let mut buffer = Vec::new();
+    let e = buffer.write_building(&building, 0).unwrap_err();
+    assert!(matches!(e.kind, ErrorKind::LengthOverflow { len: 0x10000, max: 0xFFFF }));
+    assert_eq!(e.path.to_string(), "blocks[1].metadata.gradients[0].alpha_keys");
+
+    building.blocks[1].metadata = None;
+    building.blocks[1].name = "Name".to_string();
+    let mut buffer = Vec::new();
+    buffer.write_building(&building, 0).unwrap();
+
+    // The name (length byte + 4 bytes) is followed by the enable state byte.
+    let name_offset = buffer.len() - 1 - 4 - 1;
+
+    // Cut the file inside the name of the second block.
+    let e = (&buffer[..buffer.len() - 2]).read_building().unwrap_err();
+    assert!(matches!(e.kind, ErrorKind::Truncated));
+    assert_eq!(e.path.to_string(), "blocks[1].name");
+    assert_eq!(e.offset, name_offset as u64);
+}
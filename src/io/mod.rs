@@ -1,38 +1,58 @@
 use std::{
-    collections::HashSet, io::{Read, Write}, sync::LazyLock
+    fs::{self, File}, io::{Read, Write}, path::Path, sync::atomic::{AtomicU64, Ordering}
 };
 
-mod version;
-mod utils;
+mod bytemap;
+mod convert;
+mod error;
+mod probe;
+pub(crate) mod version;
+pub(crate) mod utils;
 
-use thiserror::Error;
 use crate::structs::Building;
-use byteorder::{LE, WriteBytesExt, ReadBytesExt};
-
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-
-static NOT_INTERACTABLE: LazyLock<HashSet<u8>> = LazyLock::new(||{[
-    00, 01, 28, 33, 34, 35, 36, 37, 38,
-    59, 62, 63, 64, 65, 66, 67, 68, 69,
-    70, 71, 72, 73, 74, 75, 86, 87, 88
-].into()});
-
-static CUSTOM_BLOCKS: LazyLock<HashSet<u8>> = LazyLock::new(||[
-    109, 120, 121
-].into());
-
-#[derive(Error, Debug)]
-pub enum Error {
-    #[error("Failed to unwrap value (probably a logic error).")]
-    FailedToUnwrap,
-    #[error("Object/value/vector has too many elements.")]
-    TooManyValues,
-    #[error("The version {version:?} is not supported")]
-    UnsuportedVersion {
-        version: u8
+use byteorder::{WriteBytesExt, ReadBytesExt};
+use utils::Tracked;
+
+pub use bytemap::{byte_map, ByteMap, ByteRange};
+pub use convert::{convert, Conversion, Loss};
+pub use error::{Error, ErrorKind, FieldPath, PathSegment, Result};
+pub use probe::{detect_version, probe, probe_bytes, Confidence, ProbeReport};
+
+/// Options for [`WriteBuilding::write_building_with`].
+#[derive(Clone, Debug)]
+pub struct WriteOptions {
+    /// Format version to write.
+    pub version: u8,
+
+    /// Run [`Building::validate_for_version`] first and refuse to write a building
+    /// with diagnostics, returning [`ErrorKind::InvalidBuilding`] instead.
+    pub validate: bool,
+
+    /// Serialize the whole building into memory first and only write it to the
+    /// stream once serialization succeeded, so serialization errors never leave
+    /// partially written output behind.
+    pub atomic: bool,
+}
+
+impl WriteOptions {
+    /// Options for writing the given version without validation, straight to the stream.
+    pub fn new(version: u8) -> Self {
+        Self {
+            version,
+            validate: false,
+            atomic: false,
+        }
     }
 }
 
+/// Options for [`ReadBuilding::read_building_with`].
+#[derive(Clone, Debug, Default)]
+pub struct ReadOptions {
+    /// Read the stream to its end and keep anything after the building data in
+    /// [`Building::trailing`]. Without it, reading stops at the end of the building,
+    /// so a stream can hold more data after it.
+    pub trailing: bool,
+}
 
 /// Trait for writing a `Building` to a stream.
 ///
@@ -59,30 +79,109 @@ pub enum Error {
 /// # Partial Writes
 /// Even if an error occurs during serialization (e.g., vector sizes exceed allowed limits),
 /// some bytes may already have been written to the stream. Users should be aware that
-/// failed calls can leave the output in a partially written state.
+/// failed calls can leave the output in a partially written state, unless
+/// [`WriteOptions::atomic`] is set or [`write_building_to_path`] is used.
 pub trait WriteBuilding: Write {
     /// Writes a building to the stream using the given version.
     ///
     /// The `version` parameter controls the serialization format.  
     /// Currently supported versions:
     /// - `0`: Version 0 format.
+    /// - `6`: Version 6 format.
     ///
     /// # Errors
-    /// Returns an error if the version is unsupported or if writing fails.
+    /// Returns [`ErrorKind::UnsuportedVersion`] if the version is not supported, or
+    /// an error if writing fails.
     fn write_building(&mut self, building: &Building, version: u8) -> Result<()> {
-        self.write_u8(version)?;
+        self.write_building_with(building, &WriteOptions::new(version))
+    }
 
-        match version {
-            0 => version::v0::write_building(self, building)?,
-            _ => return Err(Box::new(Error::UnsuportedVersion { version }))
+    /// Writes a building to the stream using the given options.
+    ///
+    /// # Errors
+    /// Same as [`WriteBuilding::write_building`]. Additionally, if `options.validate`
+    /// is set and the building has diagnostics, returns [`ErrorKind::InvalidBuilding`]
+    /// before anything is written.
+    fn write_building_with(&mut self, building: &Building, options: &WriteOptions) -> Result<()> {
+        if options.validate {
+            let diagnostics = building.validate_for_version(options.version);
+            if !diagnostics.is_empty() {
+                return Err(ErrorKind::InvalidBuilding { diagnostics }.into());
+            }
         }
-        
-        Ok(())
+
+        if options.atomic {
+            let mut buffer = Vec::new();
+            // The building was validated above already.
+            buffer.write_building_with(building, &WriteOptions::new(options.version))?;
+            return Ok(self.write_all(&buffer)?);
+        }
+
+        let version = options.version;
+        let mut w = Tracked::new(self);
+        w.field("version", |w| Ok(w.write_u8(version)?))?;
+
+        let result = match version {
+            0 => version::v0::write_building(&mut w, building),
+            6 => version::v6::write_building(&mut w, building),
+            _ => Err(ErrorKind::UnsuportedVersion { version }.into())
+        };
+        let result = result.and_then(|()| w.field("trailing", |w| Ok(w.write_all(&building.trailing)?)));
+
+        w.locate(result)
     }
 }
 
 impl<W: Write + ?Sized> WriteBuilding for W {}
 
+/// Writes a building to a file, replacing it only if writing succeeded.
+///
+/// The building is written to a temporary file next to `path`, which is then
+/// renamed over `path`. On any error the temporary file is removed and an
+/// existing file at `path` is left untouched.
+///
+/// # Errors
+/// Same as [`WriteBuilding::write_building_with`], plus I/O errors from creating,
+/// syncing or renaming the file.
+pub fn write_building_to_path(path: impl AsRef<Path>, building: &Building, options: &WriteOptions) -> Result<()> {
+    let path = path.as_ref();
+    let file_name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "path has no file name")
+    })?;
+
+    // The process id keeps processes apart, the counter threads of the same process.
+    static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.{}.tmp", std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let temp_path = path.with_file_name(temp_name);
+
+    // Never touch a file this call didn't create.
+    let mut file = File::create_new(&temp_path)?;
+    let result = (|| {
+        file.write_building_with(building, &WriteOptions { atomic: true, ..options.clone() })?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Returns the exact number of bytes `write_building` produces for the building
+/// in the given version, including the version byte, without writing anything.
+///
+/// # Errors
+/// Same serialization errors as [`WriteBuilding::write_building`].
+pub fn serialized_size(building: &Building, version: u8) -> Result<u64> {
+    let mut w = Tracked::new(std::io::sink());
+    w.write_building(building, version)?;
+    Ok(w.position())
+}
+
 /// Trait for reading a `Building` from a stream.
 ///
 /// This trait extends `Read` with a version-aware method for deserializing
@@ -92,29 +191,97 @@ impl<W: Write + ?Sized> WriteBuilding for W {}
 /// # Example
 /// ```rust
 /// use sw_structure_io::structs::*;
-/// use sw_structure_io::io::ReadBuilding;
+/// use sw_structure_io::io::{ReadBuilding, WriteBuilding};
 /// use std::io::Cursor;
 ///
-/// let buffer = Cursor::new(vec![0u8; 0]); // Example buffer
-/// let mut reader = buffer;
+/// let mut buffer = Cursor::new(Vec::new());
+/// buffer.write_building(&Building::default(), 0).unwrap();
+/// buffer.set_position(0);
 ///
-/// let building = reader.read_building().unwrap();
+/// let building = buffer.read_building().unwrap();
 /// ```
 pub trait ReadBuilding: Read {
     /// Reads a building from the stream.
     ///
     /// The version is read first and determines the deserialization format.
+    /// Reading stops at the end of the building data, see
+    /// [`ReadOptions::trailing`] to keep what follows it.
+    /// Currently supported versions:
+    /// - `0`: Version 0 format.
+    /// - `6`: Version 6 format.
     ///
     /// # Errors
-    /// Returns an error if the version is unsupported or if reading fails.
+    /// Returns [`ErrorKind::UnsuportedVersion`] if the version is not supported, or
+    /// an error if reading fails.
     fn read_building(&mut self) -> Result<Building> {
-        let building = Building::default();
-        let version = self.read_u8()?;
+        self.read_building_with(&ReadOptions::default())
+    }
 
-        todo!();
-        
-        Ok(building)
+    /// Reads a building from the stream using the given options.
+    ///
+    /// # Errors
+    /// Same as [`ReadBuilding::read_building`].
+    fn read_building_with(&mut self, options: &ReadOptions) -> Result<Building> {
+        read_tracked(&mut Tracked::new(self), options)
     }
 }
 
 impl<R: Read + ?Sized> ReadBuilding for R {}
+
+fn read_tracked<R: Read>(r: &mut Tracked<R>, options: &ReadOptions) -> Result<Building> {
+    let version = r.field("version", |r| Ok(r.read_u8()?))?;
+
+    let result = match version {
+        0 => version::v0::read_building(r),
+        6 => version::v6::read_building(r),
+        _ => Err(ErrorKind::UnsuportedVersion { version }.into())
+    };
+    let result = result.and_then(|mut building| {
+        if options.trailing {
+            r.field("trailing", |r| Ok(r.read_to_end(&mut building.trailing)?))?;
+        }
+        Ok(building)
+    });
+
+    r.locate(result)
+}
+
+#[test]
+fn test_atomic_write() {
+    use crate::structs::*;
+
+    let mut building = Building::default();
+    building.roots.push(Root::default());
+    building.blocks.push(Block { name: "Block".to_string(), ..Default::default() });
+
+    let mut buffer = Vec::new();
+    buffer.write_building(&building, 0).unwrap();
+    assert_eq!(serialized_size(&building, 0).unwrap(), buffer.len() as u64);
+
+    let path = std::env::temp_dir().join(format!("sw-structure-io-{}.structure", std::process::id()));
+    write_building_to_path(&path, &building, &WriteOptions::new(0)).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), buffer);
+
+    // A block on a root that v0 can't encode fails halfway through.
+    building.blocks.push(Block { root: 0x100, ..Default::default() });
+
+    let mut buffer = Vec::new();
+    let options = WriteOptions { atomic: true, ..WriteOptions::new(0) };
+    assert!(buffer.write_building_with(&building, &options).is_err());
+    assert!(buffer.is_empty());
+
+    let original = fs::read(&path).unwrap();
+    assert!(write_building_to_path(&path, &building, &WriteOptions::new(0)).is_err());
+    assert_eq!(fs::read(&path).unwrap(), original);
+    fs::remove_file(&path).unwrap();
+
+    // Concurrent writes to the same path each use their own temporary file.
+    building.blocks.pop();
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| write_building_to_path(&path, &building, &WriteOptions::new(0)).unwrap());
+        }
+    });
+    assert_eq!(fs::read(&path).unwrap(), original);
+    fs::remove_file(&path).unwrap();
+}
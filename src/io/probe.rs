@@ -0,0 +1,220 @@
+use std::io::Read;
+
+use crate::io::{version, Error, ErrorKind, ReadBuilding, ReadOptions};
+
+/// How confident [`probe`] is that the data is a structure file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    /// Empty data or an unknown version byte.
+    None,
+    /// The version byte is known, but the body couldn't be checked or doesn't
+    /// look like that version.
+    Low,
+    /// The body parses, but there is unexpected data after it.
+    Medium,
+    /// The body parses and consumes the data exactly.
+    High,
+}
+
+/// What [`probe`] found out about the data.
+#[derive(Debug)]
+pub struct ProbeReport {
+    /// The leading version byte, `None` if the data is empty.
+    pub version: Option<u8>,
+
+    /// Whether this library can read `version`.
+    pub supported: bool,
+
+    /// Root and block counts from the header, if the version is supported and
+    /// the header is complete.
+    pub counts: Option<(u16, u16)>,
+
+    /// Whether the remaining data is long enough to hold `counts` roots and blocks.
+    pub counts_plausible: bool,
+
+    /// Whether a full parse was attempted. It is skipped if the version is
+    /// unsupported or the counts are implausible.
+    pub parsed: bool,
+
+    /// Why the header or the full parse failed.
+    pub parse_error: Option<Error>,
+
+    /// Bytes left over after a successful parse.
+    pub trailing_bytes: usize,
+
+    /// Overall verdict.
+    pub confidence: Confidence,
+}
+
+/// Returns the version a structure file claims, without checking anything else.
+///
+/// Returns `None` if the data is empty or the version byte is above the latest
+/// known version.
+pub fn detect_version(data: &[u8]) -> Option<u8> {
+    data.first().copied().filter(|&v| v <= version::LATEST)
+}
+
+/// Checks whether `r` contains a structure file.
+///
+/// Reads the whole stream, then checks the version byte, compares the root and
+/// block counts against the remaining length and finally parses the body with
+/// [`ReadBuilding`]. Garbage input is reported, never panicked on.
+///
+/// # Errors
+/// Only I/O errors from reading the stream are returned.
+pub fn probe<R: Read>(mut r: R) -> std::io::Result<ProbeReport> {
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+    Ok(probe_bytes(&data))
+}
+
+/// Same as [`probe`], for data already in memory.
+pub fn probe_bytes(data: &[u8]) -> ProbeReport {
+    let mut report = ProbeReport {
+        version: data.first().copied(),
+        supported: false,
+        counts: None,
+        counts_plausible: false,
+        parsed: false,
+        parse_error: None,
+        trailing_bytes: 0,
+        confidence: Confidence::None,
+    };
+
+    let Some(version) = report.version else {
+        return report;
+    };
+    if version > version::LATEST {
+        return report;
+    }
+    report.confidence = Confidence::Low;
+
+    let body = &data[1..];
+    let header = match version {
+        0 => v0_header(body),
+        6 => v6_header(body),
+        _ => return report,
+    };
+    report.supported = true;
+
+    let Some(header) = header else {
+        report.parse_error = Some(ErrorKind::Truncated.into());
+        return report;
+    };
+    report.counts = Some((header.roots, header.blocks));
+    report.counts_plausible = header.min_size <= body.len();
+    if !report.counts_plausible {
+        return report;
+    }
+
+    let mut rest = data;
+    report.parsed = true;
+    match rest.read_building_with(&ReadOptions { trailing: true }) {
+        Ok(building) => {
+            report.trailing_bytes = building.trailing.len();
+            report.confidence = if building.trailing.is_empty() { Confidence::High } else { Confidence::Medium };
+        }
+        Err(e) => report.parse_error = Some(e),
+    }
+
+    report
+}
+
+struct Header {
+    roots: u16,
+    blocks: u16,
+    /// Smallest possible body size for these counts.
+    min_size: usize,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([*data.get(offset)?, *data.get(offset + 1)?]))
+}
+
+fn v0_header(body: &[u8]) -> Option<Header> {
+    // position + rotation
+    const ROOT_SIZE: usize = 24;
+    // position, rotation, id, root, flags, enable_state_current, enable_state
+    const MIN_BLOCK_SIZE: usize = 12 + 6 + 1 + 1 + 1 + 1 + 1;
+
+    let roots = u16_at(body, 0)?;
+    let blocks_offset = 2 + roots as usize * ROOT_SIZE;
+    let blocks = u16_at(body, blocks_offset)?;
+
+    Some(Header {
+        roots,
+        blocks,
+        min_size: blocks_offset + 2 + blocks as usize * MIN_BLOCK_SIZE,
+    })
+}
+
+fn v6_header(body: &[u8]) -> Option<Header> {
+    // position, rotation, bounds center and size, last block index
+    const ROOT_SIZE: usize = 12 + 12 + 12 + 12 + 2;
+    // position, id, flags, enable_state_current, enable_state (+ rotation)
+    const MIN_BLOCK_SIZE: usize = 6 + 1 + 1 + 1 + 1;
+
+    let colors = match *body.first()? {
+        0xFF => 0,
+        len => len as usize,
+    };
+    let rotations_offset = 1 + colors * 2;
+    let (rotations, rotation_size) = match u16_at(body, rotations_offset)? {
+        0xFFFF => (0, 6),
+        len if len <= 0xFF => (len as usize, 1),
+        len => (len as usize, 2),
+    };
+    let roots_offset = rotations_offset + 2 + rotations * 6;
+    let roots = u16_at(body, roots_offset)?;
+    let blocks_offset = roots_offset + 2 + roots as usize * ROOT_SIZE;
+    let blocks = u16_at(body, blocks_offset)?;
+
+    Some(Header {
+        roots,
+        blocks,
+        min_size: blocks_offset + 2 + blocks as usize * (MIN_BLOCK_SIZE + rotation_size),
+    })
+}
+
+#[test]
+fn test_probe() {
+    use crate::io::WriteBuilding;
+    use crate::structs::*;
+
+    let mut building = Building::default();
+    building.roots.push(Root::default());
+    building.blocks.push(Block::default());
+
+    for version in [0, 6] {
+        let mut buffer = Vec::new();
+        buffer.write_building(&building, version).unwrap();
+
+        let report = probe_bytes(&buffer);
+        assert_eq!(report.version, Some(version));
+        assert_eq!(report.counts, Some((1, 1)));
+        assert_eq!(report.confidence, Confidence::High);
+
+        buffer.push(0);
+        assert_eq!(probe_bytes(&buffer).confidence, Confidence::Medium);
+
+        buffer.truncate(buffer.len() - 3);
+        let report = probe_bytes(&buffer);
+        assert_eq!(report.confidence, Confidence::Low);
+        assert!(!report.counts_plausible || report.parse_error.is_some());
+    }
+
+    assert_eq!(probe_bytes(&[]).confidence, Confidence::None);
+    assert_eq!(probe_bytes(&[200, 1, 2, 3]).confidence, Confidence::None);
+    assert_eq!(probe_bytes(&[3, 1, 2, 3]).confidence, Confidence::Low);
+    assert_eq!(probe_bytes(&[0, 0xFF, 0xFF]).confidence, Confidence::Low);
+
+    // Garbage must never panic.
+    use rand::Rng;
+    let mut rng = rand::rng();
+    for _ in 0..1000 {
+        let mut data = vec![0u8; rng.random_range(1..256)];
+        rng.fill(&mut data[..]);
+        data[0] = if rng.random() { 0 } else { 6 };
+        probe_bytes(&data);
+    }
+}
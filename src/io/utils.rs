@@ -1,58 +1,19 @@
-use byteorder::{WriteBytesExt, LE};
-use num_traits::{FromPrimitive, PrimInt, ToBytes, Unsigned};
-use std::io;
-use std::io::{Error, ErrorKind, Read, Write};
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use num_traits::{Bounded, FromPrimitive, PrimInt, ToPrimitive, Unsigned};
+use std::io::{Read, Write};
 
+use crate::io::bytemap::ByteRange;
+use crate::io::error::{ErrorKind, FieldPath, PathSegment, Result};
 use crate::structs::Gradient;
 
+pub(crate) use crate::spatial::Bounds;
+
 const ROTATION_MULTIPLIER: f32 = (u16::MAX as f32) / 360.0f32;
 const ROTATION_INV: f32 = 360.0 / (u16::MAX as f32);
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-
-#[derive(Clone)]
-pub(crate) struct Bounds {
-    pub(crate) min: [f32; 3],
-    pub(crate) max: [f32; 3],
-}
-
+/// Encoding of block positions relative to the bounds of their root (v6).
 impl Bounds {
-    pub(crate) const fn new() -> Self {
-        Bounds {
-            min: [f32::INFINITY; 3],
-            max: [f32::NEG_INFINITY; 3],
-        }
-    }
-
-    pub(crate) const fn from_center_and_size(center: [f32; 3], size: [f32; 3]) -> Self {
-        let mut min = [0.0f32; 3];
-        let mut max = [0.0f32; 3];
-
-        let mut i = 0;
-        while i < 3 {
-            min[i] = center[i] - size[i] * 0.5;
-            max[i] = center[i] + size[i] * 0.5;
-            i += 1;
-        }
-
-        Self { min, max }
-    }
-
-    pub(crate) const fn get_center_and_size(&self) -> ([f32; 3], [f32; 3]) {
-        let mut center = [0.0f32; 3];
-        let mut size = [0.0f32; 3];
-
-        let mut i = 0;
-        while i < 3 {
-            center[i] = (self.min[i] + self.max[i]) * 0.5;
-            size[i] = self.max[i] - self.min[i];
-            i += 1;
-        }
-
-        (center, size)
-    }
-
-    pub(crate) fn to_inbounds(&self, f: [f32; 3]) -> [i16; 3] {
+    pub(crate) fn to_inbounds(self, f: [f32; 3]) -> [i16; 3] {
         let (center, size) = self.get_center_and_size();
 
         let mut result = [0i16; 3];
@@ -63,7 +24,7 @@ impl Bounds {
         result
     }
 
-    pub(crate) fn to_global(&self, v: [i16; 3]) -> [f32; 3] {
+    pub(crate) fn to_global(self, v: [i16; 3]) -> [f32; 3] {
         let (center, size) = self.get_center_and_size();
 
         let mut result = [0.0f32; 3];
@@ -73,13 +34,6 @@ impl Bounds {
         }
         result
     }
-
-    pub(crate) fn encapsulate(&mut self, block_position: &[f32; 3]) {
-        for i in 0..3 {
-            self.min[i] = self.min[i].min(block_position[i]);
-            self.max[i] = self.max[i].max(block_position[i]);
-        }
-    }
 }
 
 pub(crate) fn pack_rotation(data: [f32; 3]) -> [u16; 3] {
@@ -112,8 +66,20 @@ pub(crate) fn unpack_rotation(data: [u16; 3]) -> [f32; 3] {
     ]
 }
 
+/// Meaning of the bits of the block flags byte, lowest bit first.
+pub(crate) const BLOCK_FLAGS: [&str; 8] = [
+    "has_name",
+    "has_connections",
+    "no_metadata",
+    "no_color",
+    "no_load",
+    "always_set",
+    "enable_state_current_above_one",
+    "enable_state_current_nonzero",
+];
+
 pub(crate) fn pack_bools(bools: &[bool]) -> Vec<u8> {
-    let mut bytes = Vec::with_capacity((bools.len() + 7) / 8);
+    let mut bytes = Vec::with_capacity(bools.len().div_ceil(8));
     for chunk in bools.chunks(8) {
         let mut byte = 0u8;
         for (i, &b) in chunk.iter().enumerate() {
@@ -137,25 +103,171 @@ pub(crate) fn unpack_bools(bytes: &[u8], count: usize) -> Vec<bool> {
     bools
 }
 
+/// Stream wrapper that keeps track of the byte offset and of the field currently
+/// being read or written, so errors can point at the exact location.
+///
+/// A recording stream also remembers which field every byte belongs to, for
+/// [`crate::io::byte_map`].
+pub(crate) struct Tracked<S> {
+    inner: S,
+    position: u64,
+    path: Vec<PathSegment>,
+    recording: Option<Recording>,
+}
+
+#[derive(Default)]
+struct Recording {
+    ranges: Vec<ByteRange>,
+    /// End of the last recorded range.
+    end: u64,
+    /// Decoded bits for the next recorded range.
+    bits: Vec<(&'static str, bool)>,
+}
+
+impl<S> Tracked<S> {
+    pub(crate) fn new(inner: S) -> Self {
+        Self {
+            inner,
+            position: 0,
+            path: Vec::new(),
+            recording: None,
+        }
+    }
+
+    /// Creates a stream that records the byte ranges of the innermost fields.
+    pub(crate) fn recording(inner: S) -> Self {
+        Self {
+            recording: Some(Recording::default()),
+            ..Self::new(inner)
+        }
+    }
+
+    /// Returns the recorded ranges, ordered and without gaps up to the current
+    /// position.
+    pub(crate) fn take_ranges(&mut self) -> Vec<ByteRange> {
+        self.record();
+        self.recording.as_mut().map(|r| std::mem::take(&mut r.ranges)).unwrap_or_default()
+    }
+
+    /// Names the bits of the byte just read, if recording.
+    pub(crate) fn bits(&mut self, names: &[&'static str], byte: u8) {
+        if let Some(recording) = &mut self.recording {
+            recording.bits = names.iter().enumerate().map(|(i, &name)| (name, byte >> i & 1 != 0)).collect();
+        }
+    }
+
+    /// Assigns the bytes since the last recorded range to the current field.
+    fn record(&mut self) {
+        if let Some(recording) = &mut self.recording
+            && self.position > recording.end
+        {
+            recording.ranges.push(ByteRange {
+                start: recording.end,
+                end: self.position,
+                path: FieldPath(self.path.clone()),
+                bits: std::mem::take(&mut recording.bits),
+            });
+            recording.end = self.position;
+        }
+    }
+
+    /// Number of bytes read or written so far.
+    pub(crate) fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Runs `f` inside the named field.
+    pub(crate) fn field<T>(&mut self, name: &'static str, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.scope(PathSegment::Field(name), f)
+    }
+
+    /// Runs `f` inside the list element with the given index.
+    pub(crate) fn index<T>(&mut self, index: usize, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.scope(PathSegment::Index(index), f)
+    }
+
+    fn scope<T>(&mut self, segment: PathSegment, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let start = self.position;
+        self.record();
+        self.path.push(segment);
+        let result = f(self);
+        self.record();
+        let result = result.map_err(|mut e| {
+            // Only the innermost scope knows where the error happened.
+            if e.path.is_empty() {
+                e.path = FieldPath(self.path.clone());
+                e.offset = start;
+            }
+            e
+        });
+        self.path.pop();
+        result
+    }
+
+    /// Attaches the current location to errors raised outside of any field.
+    pub(crate) fn locate<T>(&self, result: Result<T>) -> Result<T> {
+        result.map_err(|mut e| {
+            if e.path.is_empty() {
+                e.offset = self.position;
+            }
+            e
+        })
+    }
+}
+
+impl<R: Read> Read for Tracked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Tracked<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Converts a length into the integer type of its length field.
+pub(crate) fn length<N: FromPrimitive + Bounded + ToPrimitive>(len: usize) -> Result<N> {
+    N::from_usize(len).ok_or_else(|| {
+        ErrorKind::LengthOverflow { len, max: N::max_value().to_usize().unwrap_or(usize::MAX) }.into()
+    })
+}
+
+/// Converts a value (usually an index) into a narrower integer type.
+pub(crate) fn narrow<N: FromPrimitive + Bounded + ToPrimitive>(value: usize) -> Result<N> {
+    N::from_usize(value).ok_or_else(|| {
+        ErrorKind::ValueOverflow { value, max: N::max_value().to_usize().unwrap_or(usize::MAX) }.into()
+    })
+}
+
+/// Looks up `index` in `items`, failing with a bad reference error.
+pub(crate) fn reference<T>(items: &[T], index: usize) -> Result<&T> {
+    items.get(index).ok_or_else(|| ErrorKind::BadReference { index, len: items.len() }.into())
+}
+
 pub(crate) fn read_7bit_encoded_int(mut r: impl Read) -> Result<usize> {
     let mut result: usize = 0;
-    let mut bits_read: usize = 0;
+    let mut bits_read: u32 = 0;
 
     loop {
-        let mut buf = [0u8];
-        r.read_exact(&mut buf)?;
-        let byte = buf[0];
+        let byte = r.read_u8()?;
+
+        if bits_read >= usize::BITS {
+            return Err(ErrorKind::ValueOverflow { value: usize::MAX, max: usize::MAX }.into());
+        }
 
         result |= ((byte & 0x7F) as usize) << bits_read;
         bits_read += 7;
 
-        if bits_read > (usize::BITS as usize / 7) {
-            return Err(Box::new(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Too many bytes when decoding 7-bit int.",
-            )));
-        }
-
         if (byte & 0x80) == 0 {
             break;
         }
@@ -165,20 +277,24 @@ pub(crate) fn read_7bit_encoded_int(mut r: impl Read) -> Result<usize> {
 }
 
 pub(crate) fn read_string_7bit<R: Read>(mut r: R) -> Result<String> {
-    let len = read_7bit_encoded_int(&mut r)? as usize;
-    let mut buf = vec![0u8; len];
-    r.read_exact(&mut buf)?;
-    Ok(String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?)
+    let len = read_7bit_encoded_int(&mut r)?;
+    let mut buf = Vec::new();
+    // Don't trust the length with an allocation, truncated data is caught below.
+    r.by_ref().take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(ErrorKind::Truncated.into());
+    }
+    Ok(String::from_utf8(buf)?)
 }
 
 pub(crate) fn pack_color([r, g, b]: [u8; 3]) -> u16 {
-    ((r & 0xF8) as u16) << 8 | ((g & 0xFC) as u16) << 2 | ((b & 0xF8) as u16) >> 3
+    ((r & 0xF8) as u16) << 8 | ((g & 0xFC) as u16) << 3 | ((b & 0xF8) as u16) >> 3
 }
 
 pub(crate) fn unpack_color(rgb565: u16) -> [u8; 3] {
     [
         ((rgb565 >> 8) & 0xF8) as u8,
-        ((rgb565 >> 2) & 0xFC) as u8,
+        ((rgb565 >> 3) & 0xFC) as u8,
         ((rgb565 << 3) & 0xF8) as u8,
     ]
 }
@@ -188,7 +304,7 @@ macro_rules! impl_write_array {
         fn $func_name<E: byteorder::ByteOrder>(
             &mut self,
             array: &[$elem_type],
-        ) -> std::io::Result<()> {
+        ) -> Result<()> {
             for &v in array {
                 self.$write_fn::<E>(v)?;
             }
@@ -197,18 +313,7 @@ macro_rules! impl_write_array {
     };
 }
 
-pub trait WriteUtils: Write {
-    fn write_array<T: Copy>(
-        &mut self,
-        array: &[T],
-        f: impl Fn(&mut Self, &T) -> Result<()>,
-    ) -> Result<()> {
-        for value in array.iter() {
-            f(self, value)?;
-        }
-        Ok(())
-    }
-
+pub trait WriteUtils: Write + Sized {
     fn write_7bit_encoded_int(&mut self, mut value: usize) -> Result<()> {
         while value >= 0x80 {
             self.write_all(&[((value as u8 & 0x7F) | 0x80)])?;
@@ -224,71 +329,113 @@ pub trait WriteUtils: Write {
         Ok(())
     }
 
-    /// Writes array with length. Returns error if length is bigger than N max value.
-    fn write_array_with_length<N: PrimInt + Unsigned + FromPrimitive, T: Copy>(
-        &mut self,
-        l: impl Fn(&mut Self, &N) -> Result<()>,
-        f: impl Fn(&mut Self, &T) -> Result<()>,
-        array: &[T],
-    ) -> Result<()> {
-        let len_n = N::from_usize(array.len())
-            .ok_or_else(|| Error::new(ErrorKind::Other, "Array length too big for integer type"))?;
-        l(self, &len_n)?;
-        self.write_array(array, f)?;
-        Ok(())
-    }
+    fn write_gradient(&mut self, gradient: &Gradient) -> Result<()>;
 
+    impl_write_array!(write_array_f32, f32, write_f32);
+    impl_write_array!(write_array_u16, u16, write_u16);
+    impl_write_array!(write_array_i16, i16, write_i16);
+    impl_write_array!(write_array_i32, i32, write_i32);
+}
+
+impl<W: Write> WriteUtils for Tracked<W> {
     fn write_gradient(&mut self, gradient: &Gradient) -> Result<()> {
-        self.write_u16::<LE>(u16::try_from(gradient.color_keys.len())?)?;
-        for v in gradient.color_keys.iter() {
-            self.write_array_f32::<LE>(v)?;
-        }
+        self.field("color_keys", |w| {
+            w.write_u16::<LE>(length(gradient.color_keys.len())?)?;
+            for v in gradient.color_keys.iter() {
+                w.write_array_f32::<LE>(v)?;
+            }
+            Ok(())
+        })?;
 
-        self.write_u16::<LE>(u16::try_from(gradient.color_time_keys.len())?)?;
-        self.write_array_f32::<LE>(&gradient.color_time_keys)?;
+        self.field("color_time_keys", |w| {
+            w.write_u16::<LE>(length(gradient.color_time_keys.len())?)?;
+            w.write_array_f32::<LE>(&gradient.color_time_keys)
+        })?;
 
-        self.write_u16::<LE>(u16::try_from(gradient.alpha_keys.len())?)?;
-        self.write_array_f32::<LE>(&gradient.alpha_keys)?;
+        self.field("alpha_keys", |w| {
+            w.write_u16::<LE>(length(gradient.alpha_keys.len())?)?;
+            w.write_array_f32::<LE>(&gradient.alpha_keys)
+        })?;
 
-        self.write_u16::<LE>(u16::try_from(gradient.alpha_time_keys.len())?)?;
-        self.write_array_f32::<LE>(&gradient.alpha_time_keys)?;
+        self.field("alpha_time_keys", |w| {
+            w.write_u16::<LE>(length(gradient.alpha_time_keys.len())?)?;
+            w.write_array_f32::<LE>(&gradient.alpha_time_keys)
+        })?;
 
         Ok(())
     }
-
-    impl_write_array!(write_array_f32, f32, write_f32);
-    impl_write_array!(write_array_u16, u16, write_u16);
-    impl_write_array!(write_array_i16, i16, write_i16);
-    impl_write_array!(write_array_i32, i32, write_i32);
-    impl_write_array!(write_array_u32, u32, write_u32);
 }
 
-impl<W: Write + ?Sized> WriteUtils for W {}
-
-pub trait ReadUtils: Read {
-    fn read_array<T: Copy>(
+pub trait ReadUtils: Read + Sized {
+    /// Reads `len` elements. Each element is read in its own index scope.
+    fn read_vec<T>(
         &mut self,
         len: usize,
         f: impl Fn(&mut Self) -> Result<T>,
-    ) -> Result<Vec<T>> {
-        let mut v: Vec<T> = Vec::new();
-        v.reserve(len);
-        for _ in 0..len {
-            v.push(f(self)?);
-        }
-        Ok(v)
-    }
-    fn read_array_with_length<N: PrimInt + Unsigned + FromPrimitive, T: Copy>(
+    ) -> Result<Vec<T>>;
+
+    /// Reads array with length. The length is read first using `l`.
+    fn read_array_with_length<N: PrimInt + Unsigned + FromPrimitive, T>(
         &mut self,
         l: impl Fn(&mut Self) -> Result<N>,
         f: impl Fn(&mut Self) -> Result<T>,
     ) -> Result<Vec<T>> {
         let len_n = l(self)?;
-        self.read_array(len_n.to_usize().unwrap(), f)
+        self.read_vec(len_n.to_usize().unwrap_or(usize::MAX), f)
     }
+
+    fn read_f32_array<const N: usize>(&mut self) -> Result<[f32; N]> {
+        let mut v = [0.0f32; N];
+        self.read_f32_into::<LE>(&mut v)?;
+        Ok(v)
+    }
+
+    fn read_gradient(&mut self) -> Result<Gradient>;
 }
 
-impl<R: Read + ?Sized> ReadUtils for R {}
+impl<R: Read> ReadUtils for Tracked<R> {
+    fn read_vec<T>(
+        &mut self,
+        len: usize,
+        f: impl Fn(&mut Self) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        // The length comes from the file, so it can't be trusted with a huge allocation.
+        let mut v: Vec<T> = Vec::with_capacity(len.min(0x1000));
+        for i in 0..len {
+            v.push(self.index(i, &f)?);
+        }
+        Ok(v)
+    }
+
+    fn read_gradient(&mut self) -> Result<Gradient> {
+        let color_keys = self.field("color_keys", |r| r.read_array_with_length(
+            |r| Ok(r.read_u16::<LE>()?),
+            |r| r.read_f32_array::<4>(),
+        ))?;
+
+        let color_time_keys = self.field("color_time_keys", |r| r.read_array_with_length(
+            |r| Ok(r.read_u16::<LE>()?),
+            |r| Ok(r.read_f32::<LE>()?),
+        ))?;
+
+        let alpha_keys = self.field("alpha_keys", |r| r.read_array_with_length(
+            |r| Ok(r.read_u16::<LE>()?),
+            |r| Ok(r.read_f32::<LE>()?),
+        ))?;
+
+        let alpha_time_keys = self.field("alpha_time_keys", |r| r.read_array_with_length(
+            |r| Ok(r.read_u16::<LE>()?),
+            |r| Ok(r.read_f32::<LE>()?),
+        ))?;
+
+        Ok(Gradient {
+            color_keys,
+            color_time_keys,
+            alpha_keys,
+            alpha_time_keys,
+        })
+    }
+}
 
 #[macro_export]
 macro_rules! debug_val {
@@ -301,21 +448,21 @@ macro_rules! debug_val {
 }
 
 #[test]
-fn test_pack_unpack_roundtrip() {
-    use rand::Rng;
-
-    let mut rng = rand::rng();
-
-    for _ in 0..1000 {
-        let rgb: [u8; 3] = [rng.random(), rng.random(), rng.random()];
-        let packed = pack_color(rgb);
-        let unpacked = unpack_color(packed);
-        let repacked = pack_color(unpacked);
-
-        assert_eq!(
-            packed, repacked,
-            "Round-trip failed: original {:?}, packed {:#06x}, unpacked {:?}, repacked {:#06x}",
-            rgb, packed, unpacked, repacked
-        );
+fn test_pack_color() {
+    // Red takes bits 11-15, green bits 5-10 and blue bits 0-4.
+    for bit in 0..5 {
+        let value = 0x08u8 << bit;
+        assert_eq!(pack_color([value, 0, 0]), 0x0800 << bit);
+        assert_eq!(pack_color([0, 0, value]), 0x0001 << bit);
+        assert_eq!(unpack_color(0x0800 << bit), [value, 0, 0]);
+        assert_eq!(unpack_color(0x0001 << bit), [0, 0, value]);
+    }
+    for bit in 0..6 {
+        let value = 0x04u8 << bit;
+        assert_eq!(pack_color([0, value, 0]), 0x0020 << bit);
+        assert_eq!(unpack_color(0x0020 << bit), [0, value, 0]);
     }
+
+    // The low bits of each channel don't fit.
+    assert_eq!(pack_color([0x07, 0x03, 0x07]), 0);
 }
@@ -4,6 +4,9 @@ pub(crate) mod v0;
 // pub(crate) mod v3;
 // pub(crate) mod v4;
 // pub(crate) mod v5;
-// pub(crate) mod v6;
+pub(crate) mod v6;
 // pub(crate) mod v7;
-// pub(crate) mod v8;
\ No newline at end of file
+// pub(crate) mod v8;
+
+/// The newest format version produced by the game.
+pub(crate) const LATEST: u8 = 8;
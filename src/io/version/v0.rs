@@ -1,11 +1,11 @@
+use crate::catalog::{BlockKind, TypeSettingsKind};
+use crate::math;
 use crate::structs::*;
-use byteorder::{LE, WriteBytesExt};
-use std::{io::Write, ops::Deref};
-use crate::io::Error::*;
+use byteorder::{LE, ReadBytesExt, WriteBytesExt};
+use std::{io::{Read, Write}, ops::Deref};
+use crate::io::error::{ErrorKind, Result};
 use crate::io::utils::*;
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-
 pub(crate) struct SerializableBuilding<'a> {
     pub(crate) roots: Vec<SerializableRoot<'a>>,
     pub(crate) blocks: Vec<SerializableBlock<'a>>,
@@ -72,36 +72,42 @@ impl<'a> SerializableBuilding<'a> {
     }
 }
 
-pub(crate) fn write_building<W: Write>(mut w: W, building: &Building) -> Result<()> {
+pub(crate) fn write_building<W: Write>(w: &mut Tracked<W>, building: &Building) -> Result<()> {
     let building = SerializableBuilding::initialize(building)?;
 
-    w.write_u16::<LE>(u16::try_from(building.roots.len())?)?;
-    for root in building.roots.iter() {
-        write_root(&mut w, root, &building)?;
-    }
+    w.field("roots", |w| {
+        w.write_u16::<LE>(length(building.roots.len())?)?;
+        for (i, root) in building.roots.iter().enumerate() {
+            w.index(i, |w| write_root(w, root, &building))?;
+        }
+        Ok(())
+    })?;
+
+    w.field("blocks", |w| {
+        w.write_u16::<LE>(length(building.blocks.len())?)?;
+        for (i, block) in building.blocks.iter().enumerate() {
+            w.index(i, |w| write_block(w, block, &building))?;
+        }
+        Ok(())
+    })?;
 
-    w.write_u16::<LE>(u16::try_from(building.blocks.len())?)?;
-    for block in building.blocks.iter() {
-        write_block(&mut w, block, &building)?;
-    }
-    
     Ok(())
 }
 
-fn write_root<W: Write>(mut w: W, root: &SerializableRoot, building: &SerializableBuilding) -> Result<()> {
-    w.write_array_f32::<LE>(&root.position)?;
-    w.write_array_f32::<LE>(&root.rotation)?;
+fn write_root<W: Write>(w: &mut Tracked<W>, root: &SerializableRoot, _building: &SerializableBuilding) -> Result<()> {
+    w.field("position", |w| w.write_array_f32::<LE>(&root.position))?;
+    w.field("rotation", |w| w.write_array_f32::<LE>(&root.rotation))?;
 
     Ok(())
 }
 
-fn write_block<W: Write>(mut w: W, block: &SerializableBlock, building: &SerializableBuilding) -> Result<()> {
-    w.write_array_f32::<LE>(&block.position)?;
-    w.write_array_u16::<LE>(&pack_rotation(block.rotation))?;
+fn write_block<W: Write>(w: &mut Tracked<W>, block: &SerializableBlock, _building: &SerializableBuilding) -> Result<()> {
+    w.field("position", |w| w.write_array_f32::<LE>(&block.position))?;
+    w.field("rotation", |w| w.write_array_u16::<LE>(&pack_rotation(block.rotation)))?;
 
-    w.write_u8(block.id)?;
+    w.field("id", |w| Ok(w.write_u8(block.id.0)?))?;
 
-    w.write_u8(u8::try_from(block.root)?)?;
+    w.field("root", |w| Ok(w.write_u8(narrow(block.root as usize)?)?))?;
 
     let flags = [
         !block.name.is_empty(),
@@ -114,106 +120,454 @@ fn write_block<W: Write>(mut w: W, block: &SerializableBlock, building: &Seriali
         block.enable_state_current != 0.0f32
     ];
 
-    w.write_u8(pack_bools(&flags)[0])?;
+    w.field("flags", |w| Ok(w.write_u8(pack_bools(&flags)[0])?))?;
 
-    w.write_u8((block.enable_state_current * if flags[6] {1.0f32} else {255.0f32}) as u8)?;
+    w.field("enable_state_current", |w| {
+        Ok(w.write_u8((block.enable_state_current * if flags[6] {1.0f32} else {255.0f32}) as u8)?)
+    })?;
 
     if flags[0] {
-        w.write_string_7bit(&block.name)?;
+        w.field("name", |w| w.write_string_7bit(&block.name))?;
     }
 
-    w.write_u8((block.enable_state * 255.0f32) as u8)?;
+    w.field("enable_state", |w| Ok(w.write_u8((block.enable_state * 255.0f32) as u8)?))?;
 
-    if !flags[4] {
-        w.write_u16::<LE>(block.load.ok_or(FailedToUnwrap)?)?;
+    if let Some(load) = block.load {
+        w.field("load", |w| Ok(w.write_u16::<LE>(load)?))?;
     }
 
     if flags[1] {
-        w.write_u16::<LE>(u16::try_from(block.connections.len())?)?;
-        w.write_array_u16::<LE>(&block.connections)?;
+        w.field("connections", |w| {
+            w.write_u16::<LE>(length(block.connections.len())?)?;
+            w.write_array_u16::<LE>(&block.connections)
+        })?;
     }
 
-    if !flags[2] {
-        write_metadata(&mut w, &block, &building)?;
+    if let Some(metadata) = &block.metadata {
+        w.field("metadata", |w| write_metadata(w, metadata, block.id))?;
     }
 
-    if !flags[3] {
-        w.write_all(&block.color.ok_or(FailedToUnwrap)?)?;
+    if let Some(color) = &block.color {
+        w.field("color", |w| Ok(w.write_all(color)?))?;
     }
-    
+
     Ok(())
 }
 
-fn write_metadata<W: Write>(mut w: W, block: &SerializableBlock, building: &SerializableBuilding) -> Result<()> {
-    let metadata = block.metadata.as_ref().ok_or(FailedToUnwrap)?;
-
+pub(crate) fn write_metadata<W: Write>(w: &mut Tracked<W>, metadata: &Metadata, id: BlockKind) -> Result<()> {
     // Toggles count + toggles
-    w.write_u16::<LE>(u16::try_from(metadata.toggles.len())?)?;
-    for &v in &metadata.toggles {
-        w.write_u8(v as u8)?;
-    }
+    w.field("toggles", |w| {
+        w.write_u16::<LE>(length(metadata.toggles.len())?)?;
+        for &v in &metadata.toggles {
+            w.write_u8(v as u8)?;
+        }
+        Ok(())
+    })?;
 
     // Values count + values
-    w.write_u16::<LE>(u16::try_from(metadata.values.len())?)?;
-    w.write_array_f32::<LE>(&metadata.values)?;
+    w.field("values", |w| {
+        w.write_u16::<LE>(length(metadata.values.len())?)?;
+        w.write_array_f32::<LE>(&metadata.values)
+    })?;
 
     // Vector flag + fields count
-    let fields_len = u16::try_from(metadata.fields.len())?;
-    if fields_len > 0x7FFF {
-        return Err(Box::new(FailedToUnwrap));
-    }
-    w.write_u16::<LE>(fields_len | if metadata.vectors.is_empty() {0x0000} else {0x8000})?;
+    w.field("fields", |w| {
+        let fields_len = length::<u16>(metadata.fields.len())?;
+        if fields_len > 0x7FFF {
+            return Err(ErrorKind::LengthOverflow { len: metadata.fields.len(), max: 0x7FFF }.into());
+        }
+        Ok(w.write_u16::<LE>(fields_len | if metadata.vectors.is_empty() {0x0000} else {0x8000})?)
+    })?;
 
     // Vectors count + vectors
-    w.write_u16::<LE>(u16::try_from(metadata.vectors.len())?)?;
-    for &v in metadata.vectors.iter() {
-        w.write_array_f32::<LE>(&v)?;
-    }
+    w.field("vectors", |w| {
+        w.write_u16::<LE>(length(metadata.vectors.len())?)?;
+        for v in metadata.vectors.iter() {
+            w.write_array_f32::<LE>(v)?;
+        }
+        Ok(())
+    })?;
 
     // Fields
-    for v in &metadata.fields {
-        w.write_u16::<LE>(u16::try_from(v.len())?)?;
-        w.write_array_i32::<LE>(v)?;
-    }
+    w.field("fields", |w| {
+        for (i, v) in metadata.fields.iter().enumerate() {
+            w.index(i, |w| {
+                w.write_u16::<LE>(length(v.len())?)?;
+                w.write_array_i32::<LE>(v)
+            })?;
+        }
+        Ok(())
+    })?;
 
     // Dropdowns
-    w.write_u16::<LE>(u16::try_from(metadata.dropdowns.len())?)?;
-    w.write_array_i32::<LE>(&metadata.dropdowns)?;
+    w.field("dropdowns", |w| {
+        w.write_u16::<LE>(length(metadata.dropdowns.len())?)?;
+        w.write_array_i32::<LE>(&metadata.dropdowns)
+    })?;
 
     // Colors
-    w.write_u16::<LE>(u16::try_from(metadata.colors.len())?)?;
-    for v in &metadata.colors {
-        w.write_array_f32::<LE>(v)?;
-    }
+    w.field("colors", |w| {
+        w.write_u16::<LE>(length(metadata.colors.len())?)?;
+        for v in &metadata.colors {
+            w.write_array_f32::<LE>(v)?;
+        }
+        Ok(())
+    })?;
 
     // Gradients
-    w.write_u16::<LE>(u16::try_from(metadata.gradients.len())?)?;
-    for v in &metadata.gradients {
-        w.write_gradient(v)?;
-    }
+    w.field("gradients", |w| {
+        w.write_u16::<LE>(length(metadata.gradients.len())?)?;
+        for (i, v) in metadata.gradients.iter().enumerate() {
+            w.index(i, |w| w.write_gradient(v))?;
+        }
+        Ok(())
+    })?;
 
-    write_type_settings(&mut w, &block, &building)?;
+    w.field("type_settings", |w| write_type_settings(w, &metadata.type_settings, id))?;
 
     Ok(())
 }
 
-pub(crate) fn write_type_settings<W: Write>(mut w: W, block: &SerializableBlock, building: &SerializableBuilding) -> Result<()> {
-    let type_settings = &block.metadata.as_ref().ok_or(FailedToUnwrap)?.type_settings;
+pub(crate) fn write_type_settings<W: Write>(w: &mut Tracked<W>, type_settings: &TypeSettings, id: BlockKind) -> Result<()> {
+    if id.type_settings_kind() == TypeSettingsKind::MathBlock {
+        let (function, incoming_connections_order, slots) = match type_settings {
+            TypeSettings::MathBlock { function, incoming_connections_order, slots } => (function, incoming_connections_order, slots),
+            _ => (&String::new(), &Vec::new(), &Vec::new())
+        };
+
+        w.field("function", |w| {
+            w.write_u16::<LE>(length(function.len())?)?;
+            Ok(w.write_all(function.as_bytes())?)
+        })?;
+
+        // Neither vector stores its length, see `math_slot_count`.
+        w.field("incoming_connections_order", |w| Ok(w.write_all(incoming_connections_order)?))?;
+        w.field("slots", |w| Ok(w.write_all(slots)?))?;
+    } else if let (TypeSettingsKind::Unknown, TypeSettings::Raw(raw)) = (id.type_settings_kind(), type_settings) {
+        w.field("raw", |w| Ok(w.write_all(raw)?))?;
+    }
+
+    Ok(())
+}
 
-    match block.id {
-        129 => {
-            let (function, incoming_connections_order, slots) = match type_settings {
-                TypeSettings::MathBlock { function, incoming_connections_order, slots } => (function, incoming_connections_order, slots),
-                _ => (&String::new(), &Vec::new(), &Vec::new())
-            };
+/// Number of entries the reader expects in the incoming connections order and
+/// the slots of a math block, `None` if it can't tell.
+///
+/// Neither stores its length. Both are taken to hold one entry per input slot
+/// the function reads, which hasn't been confirmed against game files. Without
+/// a function there are none, a function that doesn't parse gives no count.
+pub(crate) fn math_slot_count(function: &str) -> Option<usize> {
+    if function.trim().is_empty() {
+        return Some(0);
+    }
+    math::parse(function).ok().map(|expr| expr.inputs().len())
+}
 
-            w.write_u16::<LE>(u16::try_from(function.len())?)?;
-            w.write_all(function.as_bytes())?;
-            w.write_all(&incoming_connections_order)?;
-            w.write_all(&slots)?;
+/// Returns `true` if the settings read back as written, i.e. the reader can
+/// tell where they end.
+pub(crate) fn type_settings_readable(type_settings: &TypeSettings, id: BlockKind) -> bool {
+    match (id.type_settings_kind(), type_settings) {
+        (TypeSettingsKind::MathBlock, TypeSettings::MathBlock { function, incoming_connections_order, slots }) => {
+            math_slot_count(function).is_some_and(|len| incoming_connections_order.len() == len && slots.len() == len)
         }
-        _ => {}
+        (TypeSettingsKind::Unknown, _) => false,
+        _ => true,
     }
+}
 
-    Ok(())
-}
\ No newline at end of file
+pub(crate) fn read_building<R: Read>(r: &mut Tracked<R>) -> Result<Building> {
+    let roots = r.field("roots", |r| r.read_array_with_length(|r| Ok(r.read_u16::<LE>()?), |r| read_root(r)))?;
+    let blocks = r.field("blocks", |r| r.read_array_with_length(|r| Ok(r.read_u16::<LE>()?), |r| read_block(r)))?;
+
+    Ok(Building { roots, blocks, trailing: Vec::new() })
+}
+
+fn read_root<R: Read>(r: &mut Tracked<R>) -> Result<Root> {
+    let position = r.field("position", |r| r.read_f32_array())?;
+    let rotation = r.field("rotation", |r| r.read_f32_array())?;
+
+    Ok(Root { position, rotation })
+}
+
+fn read_block<R: Read>(r: &mut Tracked<R>) -> Result<Block> {
+    let mut block = Block {
+        position: r.field("position", |r| r.read_f32_array())?,
+        ..Default::default()
+    };
+
+    block.rotation = r.field("rotation", |r| {
+        let mut rotation = [0u16; 3];
+        r.read_u16_into::<LE>(&mut rotation)?;
+        Ok(unpack_rotation(rotation))
+    })?;
+
+    block.id = r.field("id", |r| Ok(BlockKind(r.read_u8()?)))?;
+
+    block.root = r.field("root", |r| Ok(r.read_u8()? as u16))?;
+
+    let flags = r.field("flags", |r| {
+        let flags = r.read_u8()?;
+        r.bits(&BLOCK_FLAGS, flags);
+        Ok(unpack_bools(&[flags], 8))
+    })?;
+
+    block.enable_state_current = r.field("enable_state_current", |r| {
+        let enable_state_current = r.read_u8()? as f32;
+        Ok(match (flags[6], flags[7]) {
+            (true, _) => enable_state_current,
+            (false, true) => enable_state_current / 255.0f32,
+            (false, false) => 0.0f32,
+        })
+    })?;
+
+    if flags[0] {
+        block.name = r.field("name", |r| read_string_7bit(r))?;
+    }
+
+    block.enable_state = r.field("enable_state", |r| Ok(r.read_u8()? as f32 / 255.0f32))?;
+
+    if !flags[4] {
+        block.load = Some(r.field("load", |r| Ok(r.read_u16::<LE>()?))?);
+    }
+
+    if flags[1] {
+        block.connections = r.field("connections", |r| r.read_array_with_length(
+            |r| Ok(r.read_u16::<LE>()?),
+            |r| Ok(r.read_u16::<LE>()?),
+        ))?;
+    }
+
+    if !flags[2] {
+        let id = block.id;
+        block.metadata = Some(r.field("metadata", |r| read_metadata(r, id))?);
+    }
+
+    if !flags[3] {
+        block.color = Some(r.field("color", |r| {
+            let mut color = [0u8; 4];
+            r.read_exact(&mut color)?;
+            Ok(color)
+        })?);
+    }
+
+    Ok(block)
+}
+
+pub(crate) fn read_metadata<R: Read>(r: &mut Tracked<R>, id: BlockKind) -> Result<Metadata> {
+    // Toggles count + toggles
+    let toggles = r.field("toggles", |r| r.read_array_with_length(
+        |r| Ok(r.read_u16::<LE>()?),
+        |r| Ok(r.read_u8()? != 0),
+    ))?;
+
+    // Values count + values
+    let values = r.field("values", |r| r.read_array_with_length(
+        |r| Ok(r.read_u16::<LE>()?),
+        |r| Ok(r.read_f32::<LE>()?),
+    ))?;
+
+    // Vector flag + fields count. The flag is informational only, the vectors
+    // count is always present.
+    let fields_len = r.field("fields", |r| Ok(r.read_u16::<LE>()? & 0x7FFF))?;
+
+    // Vectors count + vectors
+    let vectors = r.field("vectors", |r| r.read_array_with_length(
+        |r| Ok(r.read_u16::<LE>()?),
+        |r| r.read_f32_array(),
+    ))?;
+
+    // Fields
+    let fields = r.field("fields", |r| r.read_vec(fields_len as usize, |r| {
+        r.read_array_with_length(
+            |r| Ok(r.read_u16::<LE>()?),
+            |r| Ok(r.read_i32::<LE>()?),
+        )
+    }))?;
+
+    // Dropdowns
+    let dropdowns = r.field("dropdowns", |r| r.read_array_with_length(
+        |r| Ok(r.read_u16::<LE>()?),
+        |r| Ok(r.read_i32::<LE>()?),
+    ))?;
+
+    // Colors
+    let colors = r.field("colors", |r| r.read_array_with_length(
+        |r| Ok(r.read_u16::<LE>()?),
+        |r| r.read_f32_array(),
+    ))?;
+
+    // Gradients
+    let gradients = r.field("gradients", |r| r.read_array_with_length(
+        |r| Ok(r.read_u16::<LE>()?),
+        |r| r.read_gradient(),
+    ))?;
+
+    let type_settings = r.field("type_settings", |r| read_type_settings(r, id))?;
+
+    Ok(Metadata {
+        toggles,
+        values,
+        fields,
+        dropdowns,
+        colors,
+        gradients,
+        vectors,
+        type_settings,
+    })
+}
+
+pub(crate) fn read_type_settings<R: Read>(r: &mut Tracked<R>, id: BlockKind) -> Result<TypeSettings> {
+    match id.type_settings_kind() {
+        TypeSettingsKind::MathBlock => {
+            let function = r.field("function", |r| {
+                let function_len = r.read_u16::<LE>()? as usize;
+                let mut function = vec![0u8; function_len];
+                r.read_exact(&mut function)?;
+                Ok(String::from_utf8(function)?)
+            })?;
+
+            let len = r.field("incoming_connections_order", |_| {
+                math_slot_count(&function).ok_or_else(|| ErrorKind::UnreadableTypeSettings { id: id.0 }.into())
+            })?;
+            let incoming_connections_order = r.field("incoming_connections_order", |r| r.read_vec(len, |r| Ok(r.read_u8()?)))?;
+            let slots = r.field("slots", |r| r.read_vec(len, |r| Ok(r.read_u8()?)))?;
+
+            Ok(TypeSettings::MathBlock { function, incoming_connections_order, slots })
+        }
+        // Neither the layout nor the length of these settings is known.
+        TypeSettingsKind::Unknown => r.field("raw", |_| Err(ErrorKind::UnreadableTypeSettings { id: id.0 }.into())),
+        TypeSettingsKind::None => Ok(TypeSettings::None)
+    }
+}
+
+#[test]
+fn test_read_write_roundtrip() {
+    use crate::io::{ReadBuilding, ReadOptions, WriteBuilding};
+
+    let mut building = Building::default();
+    building.roots.push(Root { position: [1.0, 2.0, 3.0], rotation: [0.0, 90.0, 0.0] });
+    building.roots.push(Root::default());
+
+    building.blocks.push(Block {
+        position: [0.5, -1.0, 2.0],
+        rotation: [0.0, 45.0, 270.0],
+        id: BlockKind(3),
+        name: "Block".to_string(),
+        enable_state: 1.0,
+        enable_state_current: 3.0,
+        connections: vec![1],
+        color: Some([255, 128, 0, 255]),
+        ..Default::default()
+    });
+    building.blocks.push(Block {
+        id: BlockKind(40),
+        root: 1,
+        enable_state_current: 0.5,
+        load: Some(0),
+        metadata: Some(Metadata {
+            toggles: vec![true, false, true],
+            values: vec![0.25, 4.0],
+            fields: vec![vec![1, 2], vec![]],
+            dropdowns: vec![7],
+            colors: vec![[1.0, 0.0, 0.0, 1.0]],
+            gradients: vec![Gradient {
+                color_keys: vec![[0.0, 0.0, 0.0, 1.0], [1.0, 1.0, 1.0, 1.0]],
+                color_time_keys: vec![0.0, 1.0],
+                alpha_keys: vec![1.0],
+                alpha_time_keys: vec![0.0],
+            }],
+            vectors: vec![[1.0, 2.0, 3.0]],
+            type_settings: TypeSettings::None,
+        }),
+        ..Default::default()
+    });
+    building.trailing = vec![0xAB, 0xCD];
+
+    let mut buffer = Vec::new();
+    buffer.write_building(&building, 0).unwrap();
+    assert!((&buffer[..]).read_building().unwrap().trailing.is_empty());
+    let loaded = (&buffer[..]).read_building_with(&ReadOptions { trailing: true }).unwrap();
+
+    assert_eq!(loaded.roots, building.roots);
+    assert_eq!(loaded.trailing, building.trailing);
+    assert_eq!(loaded.blocks[0].enable_state_current, 3.0);
+    assert_eq!(loaded.blocks[1].metadata, building.blocks[1].metadata);
+    assert_eq!(loaded.blocks[1].load, Some(0));
+
+    let mut rewritten = Vec::new();
+    rewritten.write_building(&loaded, 0).unwrap();
+    assert_eq!(buffer, rewritten);
+
+    // Math block settings don't store their lengths, the function tells them.
+    let building = Building {
+        roots: vec![Root::default()],
+        blocks: vec![
+            Block { connections: vec![2], ..Default::default() },
+            Block { connections: vec![2], ..Default::default() },
+            Block {
+                id: BlockKind::MATH_BLOCK,
+                metadata: Some(Metadata {
+                    values: vec![1.0],
+                    type_settings: TypeSettings::MathBlock {
+                        function: "a+b".to_string(),
+                        incoming_connections_order: vec![0, 1],
+                        slots: vec![1, 0],
+                    },
+                    ..Default::default()
+                }),
+                color: Some([1, 2, 3, 4]),
+                ..Default::default()
+            },
+        ],
+        trailing: Vec::new(),
+    };
+    let mut buffer = Vec::new();
+    buffer.write_building(&building, 0).unwrap();
+    assert!(buffer.ends_with(&[3, 0, b'a', b'+', b'b', 0, 1, 1, 0, 1, 2, 3, 4]));
+    assert_eq!((&buffer[..]).read_building().unwrap(), building);
+
+    // Settings that don't match the function are written as given.
+    let mut building = building;
+    building.blocks[2].metadata = Some(Metadata {
+        type_settings: TypeSettings::MathBlock {
+            function: "a+b".to_string(),
+            incoming_connections_order: vec![0],
+            slots: vec![1, 2],
+        },
+        ..Default::default()
+    });
+    let mut buffer = Vec::new();
+    buffer.write_building(&building, 0).unwrap();
+    assert!(buffer.ends_with(&[3, 0, b'a', b'+', b'b', 0, 1, 2, 1, 2, 3, 4]));
+
+    // A function that doesn't parse doesn't tell where the settings end.
+    building.blocks[2].metadata = Some(Metadata {
+        type_settings: TypeSettings::MathBlock {
+            function: "a+".to_string(),
+            incoming_connections_order: vec![],
+            slots: vec![],
+        },
+        ..Default::default()
+    });
+    let mut buffer = Vec::new();
+    buffer.write_building(&building, 0).unwrap();
+    let e = (&buffer[..]).read_building().unwrap_err();
+    assert!(matches!(e.kind, ErrorKind::UnreadableTypeSettings { id: 129 }));
+    assert_eq!(e.path.to_string(), "blocks[2].metadata.type_settings.incoming_connections_order");
+
+    // Custom block settings are written as they are, without a length, and can't
+    // be read back.
+    building.blocks = vec![Block { id: BlockKind(109), metadata: Some(Metadata::default()), ..Default::default() }];
+    let mut empty = Vec::new();
+    empty.write_building(&building, 0).unwrap();
+
+    building.blocks[0].metadata = Some(Metadata { type_settings: TypeSettings::Raw(vec![1, 2, 3]), ..Default::default() });
+    let mut buffer = Vec::new();
+    buffer.write_building(&building, 0).unwrap();
+    assert_eq!(buffer[..buffer.len() - 3], empty[..]);
+    assert!(buffer.ends_with(&[1, 2, 3]));
+
+    let e = (&empty[..]).read_building().unwrap_err();
+    assert!(matches!(e.kind, ErrorKind::UnreadableTypeSettings { id: 109 }));
+    assert_eq!(e.path.to_string(), "blocks[0].metadata.type_settings.raw");
+}
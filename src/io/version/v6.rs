@@ -1,279 +1,555 @@
-use crate::{
-    block::*,
-    building::*,
-    io::{utils::*, write::version},
-    root::*,
-};
-use byteorder::{LE, WriteBytesExt};
+use crate::catalog::BlockKind;
+use crate::structs::*;
+use byteorder::{LE, ReadBytesExt, WriteBytesExt};
 use indexmap::IndexSet;
-use std::io::Write;
-use std::{ops::Deref, rc::Rc};
+use std::{io::{Read, Write}, ops::Deref};
+use crate::io::error::Result;
+use crate::io::utils::*;
+use crate::io::version::v0::{read_metadata, write_metadata};
+
+/// Names of the block flag bits. Unlike v0, bit 7 is set for a zero
+/// `enable_state_current`, as in the draft of this writer the port started from.
+const BLOCK_FLAGS: [&str; 8] = {
+    let mut flags = crate::io::utils::BLOCK_FLAGS;
+    flags[7] = "enable_state_current_zero";
+    flags
+};
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+pub(crate) struct SerializableBuilding<'a> {
+    pub(crate) roots: Vec<SerializableRoot<'a>>,
 
-static VERSION: u8 = 0;
+    /// Blocks in serialization order, grouped by root.
+    pub(crate) blocks: Vec<SerializableBlock<'a>>,
 
-pub(crate) struct SerializableBuilding {
-    pub(crate) version: u8,
-    pub(crate) roots: Vec<SerializableRoot>,
-    pub(crate) blocks: Vec<SerializableBlock>,
-    pub(crate) block_ids: IndexSet<*const Block>,
+    /// Maps an index in `Building::blocks` to its index in `blocks`.
+    pub(crate) block_indices: Vec<u16>,
 
     pub(crate) rotations: Vec<[u16; 3]>,
     pub(crate) colors: Vec<u16>,
 }
 
-impl SerializableBuilding {
-    fn new(building: &Building, version: u8) -> Result<Self> {
-        // Basic initialization
-        let (mut root_count, mut block_count): (usize, usize) = (0, 0);
-        for root in building.roots.iter() {
-            root_count += 1;
-            block_count += root.blocks.borrow().len();
+pub(crate) struct SerializableRoot<'a> {
+    pub(crate) root: &'a Root,
+
+    pub(crate) bounds: Bounds,
+    pub(crate) last_block_index: u16,
+}
+
+impl Deref for SerializableRoot<'_> {
+    type Target = Root;
+    fn deref(&self) -> &Self::Target {
+        self.root
+    }
+}
+
+pub(crate) struct SerializableBlock<'a> {
+    pub(crate) block: &'a Block,
+
+    /// Index of the root in `SerializableBuilding::roots`.
+    pub(crate) root_index: usize,
+
+    pub(crate) position_inbounds: [i16; 3],
+    pub(crate) rotation_index: u16,
+    pub(crate) color_index: u8,
+}
+
+impl Deref for SerializableBlock<'_> {
+    type Target = Block;
+    fn deref(&self) -> &Self::Target {
+        self.block
+    }
+}
+
+impl<'a> SerializableBuilding<'a> {
+    fn initialize(building: &'a Building) -> Result<Self> {
+        let root_order = root_order(building);
+        let mut root_indices = vec![0usize; building.roots.len()];
+        for (new_index, &old_index) in root_order.iter().enumerate() {
+            root_indices[old_index] = new_index;
         }
-        //
-        assert!(root_count <= 0xFFFF);
-        assert!(block_count <= 0xFFFF);
-        //
-        let mut roots: Vec<SerializableRoot> = Vec::new();
-        let mut blocks: Vec<SerializableBlock> = Vec::new();
-        let mut block_ids: IndexSet<*const Block> = IndexSet::new();
-        //
-        for root in building.roots.iter() {
-            for block in root.blocks.borrow().iter() {
-                blocks.push(SerializableBlock::new(block.clone()));
-                block_ids.insert(Rc::as_ptr(block));
-            }
-            roots.push(SerializableRoot::new(root.clone()));
+
+        let mut roots: Vec<SerializableRoot<'a>> = root_order
+            .iter()
+            .map(|&i| SerializableRoot { root: &building.roots[i], bounds: Bounds::new(), last_block_index: 0 })
+            .collect();
+
+        let order = block_order(building);
+
+        length::<u16>(building.blocks.len())?;
+        let mut block_indices = vec![0u16; building.blocks.len()];
+        for (new_index, &old_index) in order.iter().enumerate() {
+            block_indices[old_index] = new_index as u16;
         }
-        // ---
-
-        // Root ids
-        // assert!(root_count <= 0xFF);
-        // let (mut root_index, mut block_index): (usize, usize) = (0, 0);
-        // for root in building.roots.iter() {
-        //     for block in root.blocks.borrow().iter() {
-        //        blocks[block_index].root_id = root_index as u8;
-        //     }
-        //     root_index += 1;
-        // }
-        // ---
+
+        let mut blocks: Vec<SerializableBlock<'a>> = order
+            .iter()
+            .map(|&i| SerializableBlock {
+                block: &building.blocks[i],
+                root_index: 0,
+                position_inbounds: [0; 3],
+                rotation_index: 0,
+                color_index: 0,
+            })
+            .collect();
 
         // Positions
-        let (mut root_index, mut block_index): (usize, usize) = (0, 0);
-        for root in building.roots.iter() {
-            for block in root.blocks.borrow().iter() {
-                roots[root_index].bounds.encapsulate(&block.position.get());
-                block_index += 1;
-            }
-            root_index += 1;
+        for block in blocks.iter_mut() {
+            block.root_index = *reference(&root_indices, block.root as usize)?;
+            roots[block.root_index].bounds.encapsulate(&block.position);
         }
-        let (mut root_index, mut block_index): (usize, usize) = (0, 0);
-        for root in building.roots.iter() {
-            for block in root.blocks.borrow().iter() {
-                blocks[block_index].position_inbounds =
-                    roots[root_index].bounds.to_inbounds(block.position.get());
-                block_index += 1;
-            }
-            root_index += 1;
+        for block in blocks.iter_mut() {
+            block.position_inbounds = roots[block.root_index].bounds.to_inbounds(block.position);
         }
-        // ---
-
-        // Last block indexes
-        let (mut root_index, mut block_index): (usize, usize) = (0, 0);
-        for root in building.roots.iter() {
-            block_index += root.blocks.borrow().len();
-            roots[root_index].last_block_index = block_index.checked_sub(1).unwrap_or(0) as u16;
-            root_index += 1;
+
+        // Last block indexes. An empty root repeats the index of the root before it.
+        let mut block_count: usize = 0;
+        for (root_index, root) in roots.iter_mut().enumerate() {
+            block_count += blocks[block_count..]
+                .iter()
+                .take_while(|b| b.root_index == root_index)
+                .count();
+            root.last_block_index = narrow(block_count.saturating_sub(1))?;
         }
-        // ---
 
         // Rotations
         let mut rotations: IndexSet<[u16; 3]> = IndexSet::new();
         for block in blocks.iter_mut() {
-            block.rotation_index =
-                rotations.insert_full(pack_rotation(block.rotation.get())).0 as u16;
+            block.rotation_index = narrow(rotations.insert_full(pack_rotation(block.rotation)).0)?;
         }
         let single_byte_rotation = rotations.len() <= 0xFF;
         let avg_rotations = blocks.len() as f32 / rotations.len() as f32;
-        if avg_rotations > (if single_byte_rotation { 1.2f32 } else { 1.5f32 }) {
+        if rotations.len() >= 0xFFFF || avg_rotations < (if single_byte_rotation { 1.2f32 } else { 1.5f32 }) {
             rotations = IndexSet::new();
         }
-        assert!(rotations.len() <= 0xFFFF);
         let rotations: Vec<[u16; 3]> = rotations.into_iter().collect();
-        // ---
 
         // Colors
         let mut colors: IndexSet<u16> = IndexSet::new();
         let mut colored_count: usize = 0;
         for block in blocks.iter_mut() {
-            if let Some(color) = block.color.get() {
-                if colors.len() >= 0xFF {
-                    break;
-                }
-                block.color_index = colors.insert_full(pack_color(color)).0 as u8;
+            if let Some([r, g, b, _]) = block.color {
+                block.color_index = colors.insert_full(pack_color([r, g, b])).0 as u8;
                 colored_count += 1;
             }
         }
         let avg_colors = colored_count as f32 / colors.len() as f32;
-        if avg_colors > 2.0f32 && colors.len() >= 0xFF {
-            colors = IndexSet::new()
+        if colors.len() >= 0xFF || avg_colors < 2.0f32 {
+            colors = IndexSet::new();
         }
         let colors: Vec<u16> = colors.into_iter().collect();
-        // ---
 
         Ok(Self {
-            version,
             roots,
             blocks,
-            block_ids,
-
+            block_indices,
             rotations,
             colors,
         })
     }
-}
 
-pub(crate) struct SerializableRoot {
-    pub(crate) root: Rc<Root>,
+    /// Converts an index into `Building::blocks` to the serialized block index.
+    fn block_index(&self, index: u16) -> Result<u16> {
+        Ok(*reference(&self.block_indices, index as usize)?)
+    }
+}
 
-    pub(crate) bounds: Bounds,
-    pub(crate) last_block_index: u16,
+/// Returns the indices of `building.roots` in the order they are serialized.
+///
+/// Every root stores the index of its last block, and an empty root repeats the
+/// index of the root before it. That doesn't work in front of the first block,
+/// index 0 would claim that block, so empty roots before the first root with
+/// blocks are moved to the end.
+pub(crate) fn root_order(building: &Building) -> Vec<usize> {
+    let first = building.blocks.iter().map(|b| b.root as usize).min().unwrap_or(0);
+    let first = first.min(building.roots.len());
+    (first..building.roots.len()).chain(0..first).collect()
 }
 
-impl Deref for SerializableRoot {
-    type Target = Rc<Root>;
-    fn deref(&self) -> &Self::Target {
-        &self.root
-    }
+/// Returns the indices of `building.blocks` in the order they are serialized.
+///
+/// Blocks don't store their root in this version, instead every root stores
+/// the index of its last block. Blocks have to be grouped by root, in the order
+/// of [`root_order`].
+pub(crate) fn block_order(building: &Building) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..building.blocks.len()).collect();
+    order.sort_by_key(|&i| building.blocks[i].root);
+    order
 }
 
-impl SerializableRoot {
-    fn new(root: Rc<Root>) -> Self {
-        Self {
-            root,
+pub(crate) fn write_building<W: Write>(w: &mut Tracked<W>, building: &Building) -> Result<()> {
+    let building = SerializableBuilding::initialize(building)?;
 
-            bounds: Bounds::new(),
-            last_block_index: 0,
+    w.field("colors", |w| {
+        if !building.colors.is_empty() {
+            w.write_u8(building.colors.len() as u8)?;
+            w.write_array_u16::<LE>(&building.colors)
+        } else {
+            Ok(w.write_u8(0xFF)?)
         }
-    }
-}
+    })?;
 
-pub(crate) struct SerializableBlock {
-    pub(crate) block: Rc<Block>,
+    w.field("rotations", |w| {
+        if !building.rotations.is_empty() {
+            w.write_u16::<LE>(building.rotations.len() as u16)?;
+            for r in building.rotations.iter() {
+                w.write_array_u16::<LE>(r)?;
+            }
+            Ok(())
+        } else {
+            Ok(w.write_u16::<LE>(0xFFFF)?)
+        }
+    })?;
 
-    pub(crate) position_inbounds: [i16; 3],
-    pub(crate) rotation_index: u16,
-    // pub(crate) root_id           : u8,
-    pub(crate) color_index: u8,
+    w.field("roots", |w| {
+        w.write_u16::<LE>(length(building.roots.len())?)?;
+        for (i, root) in building.roots.iter().enumerate() {
+            w.index(i, |w| write_root(w, root, &building))?;
+        }
+        Ok(())
+    })?;
+
+    w.field("blocks", |w| {
+        w.write_u16::<LE>(length(building.blocks.len())?)?;
+        for (i, block) in building.blocks.iter().enumerate() {
+            w.index(i, |w| write_block(w, block, &building))?;
+        }
+        Ok(())
+    })?;
+
+    Ok(())
 }
 
-impl Deref for SerializableBlock {
-    type Target = Rc<Block>;
-    fn deref(&self) -> &Self::Target {
-        &self.block
-    }
+fn write_root<W: Write>(w: &mut Tracked<W>, root: &SerializableRoot, _building: &SerializableBuilding) -> Result<()> {
+    w.field("position", |w| w.write_array_f32::<LE>(&root.position))?;
+    w.field("rotation", |w| w.write_array_f32::<LE>(&root.rotation))?;
+
+    // Roots without blocks never encapsulated anything.
+    let (center, size) = if root.bounds.min[0] <= root.bounds.max[0] {
+        root.bounds.get_center_and_size()
+    } else {
+        ([0.0f32; 3], [0.0f32; 3])
+    };
+    w.field("bounds", |w| {
+        w.field("center", |w| w.write_array_f32::<LE>(&center))?;
+        w.field("size", |w| w.write_array_f32::<LE>(&size))
+    })?;
+
+    w.field("last_block_index", |w| Ok(w.write_u16::<LE>(root.last_block_index)?))?;
+
+    Ok(())
 }
 
-impl SerializableBlock {
-    fn new(block: Rc<Block>) -> Self {
-        Self {
-            block,
+fn write_block<W: Write>(w: &mut Tracked<W>, block: &SerializableBlock, building: &SerializableBuilding) -> Result<()> {
+    w.field("position", |w| w.write_array_i16::<LE>(&block.position_inbounds))?;
 
-            position_inbounds: [0; 3],
-            rotation_index: 0,
-            // root_id: 0,
-            color_index: 0,
+    w.field("rotation", |w| {
+        if !building.rotations.is_empty() {
+            if building.rotations.len() <= 0xFF {
+                Ok(w.write_u8(block.rotation_index as u8)?)
+            } else {
+                Ok(w.write_u16::<LE>(block.rotation_index)?)
+            }
+        } else {
+            w.write_array_u16::<LE>(&pack_rotation(block.rotation))
         }
-    }
-}
+    })?;
 
-pub(crate) fn write_building<W: Write>(mut w: W, building: &Building) -> Result<()> {
-    let building = SerializableBuilding::new(building, VERSION)?;
+    w.field("id", |w| Ok(w.write_u8(block.id.0)?))?;
 
-    w.write_u8(VERSION)?;
+    let flags = [
+        !block.name.is_empty(),
+        !block.connections.is_empty(),
+        block.metadata.is_none(),
+        block.color.is_none(),
+        block.load.is_none(),
+        true,
+        block.enable_state_current > 1.0f32,
+        block.enable_state_current == 0.0f32
+    ];
 
-    if !building.colors.is_empty() {
-        w.write_u8(building.colors.len() as u8)?;
-        w.write_array_u16::<LE>(&building.colors)?;
-    } else {
-        w.write_u8(0xFF)?;
+    w.field("flags", |w| Ok(w.write_u8(pack_bools(&flags)[0])?))?;
+
+    w.field("enable_state_current", |w| {
+        Ok(w.write_u8((block.enable_state_current * if flags[6] {1.0f32} else {255.0f32}) as u8)?)
+    })?;
+
+    if flags[0] {
+        w.field("name", |w| w.write_string_7bit(&block.name))?;
     }
 
-    if !building.rotations.is_empty() {
-        w.write_u16::<LE>(building.rotations.len() as u16)?;
-        for r in building.rotations.iter() {
-            w.write_array_u16::<LE>(r)?;
-        }
-    } else {
-        w.write_u16::<LE>(0xFFFF)?;
+    w.field("enable_state", |w| Ok(w.write_u8((block.enable_state * 255.0f32) as u8)?))?;
+
+    if let Some(load) = block.load {
+        w.field("load", |w| Ok(w.write_u16::<LE>(building.block_index(load)?)?))?;
     }
 
-    w.write_u16::<LE>(u16::try_from(building.roots.len())?)?;
-    for root in building.roots.iter() {
-        write_root(&mut w, &root, &building)?;
+    if flags[1] {
+        w.field("connections", |w| {
+            w.write_u16::<LE>(length(block.connections.len())?)?;
+            for (i, &c) in block.connections.iter().enumerate() {
+                w.index(i, |w| Ok(w.write_u16::<LE>(building.block_index(c)?)?))?;
+            }
+            Ok(())
+        })?;
     }
 
-    w.write_u16::<LE>(u16::try_from(building.blocks.len())?)?;
-    for block in building.blocks.iter() {
-        write_block(&mut w, &block, &building)?;
+    if let Some(metadata) = &block.metadata {
+        w.field("metadata", |w| match &metadata.type_settings {
+            TypeSettings::MathBlock { function, incoming_connections_order, slots } => {
+                let incoming_connections_order = incoming_connections_order
+                    .iter()
+                    .map(|&i| narrow(building.block_index(i as u16)? as usize))
+                    .collect::<Result<Vec<u8>>>()?;
+                let metadata = Metadata {
+                    type_settings: TypeSettings::MathBlock {
+                        function: function.clone(),
+                        incoming_connections_order,
+                        slots: slots.clone(),
+                    },
+                    ..metadata.clone()
+                };
+                write_metadata(w, &metadata, block.id)
+            }
+            _ => write_metadata(w, metadata, block.id),
+        })?;
+    }
+
+    if let Some([r, g, b, _]) = block.color {
+        w.field("color", |w| {
+            if !building.colors.is_empty() {
+                Ok(w.write_u8(block.color_index)?)
+            } else {
+                Ok(w.write_u16::<LE>(pack_color([r, g, b]))?)
+            }
+        })?;
     }
 
     Ok(())
 }
 
-fn write_root<W: Write>(
-    mut w: W,
-    root: &SerializableRoot,
-    building: &SerializableBuilding,
-) -> Result<()> {
-    w.write_array_f32::<LE>(&root.position.get())?;
-    w.write_array_f32::<LE>(&root.rotation.get())?;
+pub(crate) fn read_building<R: Read>(r: &mut Tracked<R>) -> Result<Building> {
+    let colors = r.field("colors", |r| match r.read_u8()? {
+        0xFF => Ok(Vec::new()),
+        len => r.read_vec(len as usize, |r| Ok(r.read_u16::<LE>()?)),
+    })?;
+
+    let rotations = r.field("rotations", |r| match r.read_u16::<LE>()? {
+        0xFFFF => Ok(Vec::new()),
+        len => r.read_vec(len as usize, |r| {
+            let mut rotation = [0u16; 3];
+            r.read_u16_into::<LE>(&mut rotation)?;
+            Ok(rotation)
+        }),
+    })?;
+
+    let roots = r.field("roots", |r| r.read_array_with_length(|r| Ok(r.read_u16::<LE>()?), |r| read_root(r)))?;
+
+    let blocks = r.field("blocks", |r| {
+        let block_count = r.read_u16::<LE>()?;
+        let mut blocks = Vec::with_capacity(block_count as usize);
+        let mut root_index: usize = 0;
+        for index in 0..block_count {
+            while root_index + 1 < roots.len() && index > roots[root_index].last_block_index {
+                root_index += 1;
+            }
+            let root = reference(&roots, root_index)?;
+            blocks.push(r.index(index as usize, |r| read_block(r, root, root_index as u16, &rotations, &colors))?);
+        }
+        Ok(blocks)
+    })?;
+
+    Ok(Building {
+        roots: roots.into_iter().map(|r| r.root).collect(),
+        blocks,
+        trailing: Vec::new(),
+    })
+}
 
-    let (center, size) = root.bounds.get_center_and_size();
-    w.write_array_f32::<LE>(&center)?;
-    w.write_array_f32::<LE>(&size)?;
+struct DeserializedRoot {
+    root: Root,
+    bounds: Bounds,
+    last_block_index: u16,
+}
 
-    w.write_u16::<LE>(root.last_block_index)?;
+fn read_root<R: Read>(r: &mut Tracked<R>) -> Result<DeserializedRoot> {
+    let position = r.field("position", |r| r.read_f32_array())?;
+    let rotation = r.field("rotation", |r| r.read_f32_array())?;
 
-    Ok(())
-}
+    let (center, size) = r.field("bounds", |r| {
+        let center = r.field("center", |r| r.read_f32_array())?;
+        let size = r.field("size", |r| r.read_f32_array())?;
+        Ok((center, size))
+    })?;
 
-fn write_block<W: Write>(
-    mut w: W,
-    block: &SerializableBlock,
-    building: &SerializableBuilding,
-) -> Result<()> {
-    w.write_array_f32::<LE>(&block.position.get())?;
+    let last_block_index = r.field("last_block_index", |r| Ok(r.read_u16::<LE>()?))?;
 
-    if !building.rotations.is_empty() {
-        if building.rotations.len() <= 0xFF {
-            w.write_u8(u8::try_from(block.rotation_index)?)?;
+    Ok(DeserializedRoot {
+        root: Root { position, rotation },
+        bounds: Bounds::from_center_and_size(center, size),
+        last_block_index,
+    })
+}
+
+fn read_block<R: Read>(
+    r: &mut Tracked<R>,
+    root: &DeserializedRoot,
+    root_index: u16,
+    rotations: &[[u16; 3]],
+    colors: &[u16],
+) -> Result<Block> {
+    let mut block = Block {
+        root: root_index,
+        ..Default::default()
+    };
+
+    block.position = r.field("position", |r| {
+        let mut position = [0i16; 3];
+        r.read_i16_into::<LE>(&mut position)?;
+        Ok(root.bounds.to_global(position))
+    })?;
+
+    block.rotation = r.field("rotation", |r| {
+        let rotation = if !rotations.is_empty() {
+            let index = if rotations.len() <= 0xFF {
+                r.read_u8()? as usize
+            } else {
+                r.read_u16::<LE>()? as usize
+            };
+            *reference(rotations, index)?
         } else {
-            w.write_u16::<LE>(block.rotation_index)?;
-        }
-    } else {
-        w.write_array_u16::<LE>(&pack_rotation(block.rotation.get()))?;
+            let mut rotation = [0u16; 3];
+            r.read_u16_into::<LE>(&mut rotation)?;
+            rotation
+        };
+        Ok(unpack_rotation(rotation))
+    })?;
+
+    block.id = r.field("id", |r| Ok(BlockKind(r.read_u8()?)))?;
+
+    let flags = r.field("flags", |r| {
+        let flags = r.read_u8()?;
+        r.bits(&BLOCK_FLAGS, flags);
+        Ok(unpack_bools(&[flags], 8))
+    })?;
+
+    block.enable_state_current = r.field("enable_state_current", |r| {
+        let enable_state_current = r.read_u8()? as f32;
+        Ok(match (flags[6], flags[7]) {
+            (true, _) => enable_state_current,
+            (false, true) => 0.0f32,
+            (false, false) => enable_state_current / 255.0f32,
+        })
+    })?;
+
+    if flags[0] {
+        block.name = r.field("name", |r| read_string_7bit(r))?;
     }
 
-    w.write_u8(block.id.get())?;
+    block.enable_state = r.field("enable_state", |r| Ok(r.read_u8()? as f32 / 255.0f32))?;
 
-    // w.write_u8(block.root_id)?;
+    if !flags[4] {
+        block.load = Some(r.field("load", |r| Ok(r.read_u16::<LE>()?))?);
+    }
 
-    let esc_is_high = block.enable_state_current.get() > 1.0f32;
-    let esc_is_zero = block.enable_state_current.get() == 0.0f32;
+    if flags[1] {
+        block.connections = r.field("connections", |r| r.read_array_with_length(
+            |r| Ok(r.read_u16::<LE>()?),
+            |r| Ok(r.read_u16::<LE>()?),
+        ))?;
+    }
 
-    let flags = 
-    // ---------------------------------------------------- //
-        (block.name.borrow().is_some()                as u8) << 0 |
-        ((block.connections.borrow().len() > 0)       as u8) << 1 |
-        (block.metadata.borrow().is_none()            as u8) << 2 |
-        (block.color.get().is_none()                  as u8) << 3 |
-        (block.load.borrow()                          as u8) << 4 |
-        (true                                         as u8) << 5 |
-        ((block.enable_state_current.get() >  1.0f32) as u8) << 6 |
-        ((block.enable_state_current.get() == 0.0f32) as u8) << 7 ;
-    // ---------------------------------------------------- //
+    if !flags[2] {
+        let id = block.id;
+        block.metadata = Some(r.field("metadata", |r| read_metadata(r, id))?);
+    }
 
-    Ok(())
+    if !flags[3] {
+        block.color = Some(r.field("color", |r| {
+            let packed = if !colors.is_empty() {
+                *reference(colors, r.read_u8()? as usize)?
+            } else {
+                r.read_u16::<LE>()?
+            };
+            let [r, g, b] = unpack_color(packed);
+            Ok([r, g, b, 0xFF])
+        })?);
+    }
+
+    Ok(block)
+}
+
+#[test]
+fn test_read_write_roundtrip() {
+    use crate::io::{ReadBuilding, WriteBuilding};
+
+    let mut building = Building::default();
+    building.roots.push(Root::default());
+    building.roots.push(Root { position: [0.0, 5.0, 0.0], rotation: [0.0, 0.0, 90.0] });
+
+    for i in 0..20u16 {
+        building.blocks.push(Block {
+            position: [i as f32, (i % 3) as f32, -(i as f32) * 0.5],
+            rotation: [0.0, 90.0 * (i % 2) as f32, 0.0],
+            id: BlockKind(1),
+            // Blocks of both roots are interleaved on purpose.
+            root: i % 2,
+            connections: if i > 0 { vec![i - 1] } else { vec![] },
+            // Exactly representable in RGB565.
+            color: Some([248, 0, 0, 255]),
+            ..Default::default()
+        });
+    }
+
+    let mut buffer = Vec::new();
+    buffer.write_building(&building, 6).unwrap();
+    let loaded = (&buffer[..]).read_building().unwrap();
+
+    assert_eq!(loaded.roots, building.roots);
+    assert_eq!(loaded.blocks.len(), building.blocks.len());
+
+    // Blocks come back grouped by root, with connections following them.
+    let mut order: Vec<usize> = (0..building.blocks.len()).collect();
+    order.sort_by_key(|&i| building.blocks[i].root);
+    for (loaded_block, &i) in loaded.blocks.iter().zip(order.iter()) {
+        let block = &building.blocks[i];
+        assert_eq!(loaded_block.root, block.root);
+        assert_eq!(loaded_block.color, block.color);
+        for (a, b) in loaded_block.position.iter().zip(block.position.iter()) {
+            assert!((a - b).abs() < 0.01, "{:?} != {:?}", loaded_block.position, block.position);
+        }
+        let connections: Vec<usize> = loaded_block.connections.iter().map(|&c| order[c as usize]).collect();
+        let expected: Vec<usize> = block.connections.iter().map(|&c| c as usize).collect();
+        assert_eq!(connections, expected);
+    }
+
+    // Empty roots between and after roots with blocks keep their blocks apart.
+    building.roots.insert(1, Root { position: [0.0, 1.0, 0.0], ..Default::default() });
+    building.roots.push(Root::default());
+    for block in building.blocks.iter_mut() {
+        block.root *= 2;
+    }
+    let mut buffer = Vec::new();
+    buffer.write_building(&building, 6).unwrap();
+    let loaded = (&buffer[..]).read_building().unwrap();
+    assert_eq!(loaded.roots, building.roots);
+    assert_eq!(loaded.blocks.iter().filter(|b| b.root == 0).count(), 10);
+    assert_eq!(loaded.blocks.iter().filter(|b| b.root == 2).count(), 10);
+
+    // An empty root 0 can't be told apart from a root holding block 0, it moves
+    // to the end.
+    for block in building.blocks.iter_mut() {
+        block.root = block.root.max(1);
+    }
+    let mut buffer = Vec::new();
+    buffer.write_building(&building, 6).unwrap();
+    let loaded = (&buffer[..]).read_building().unwrap();
+    assert_eq!(loaded.roots[..3], building.roots[1..]);
+    assert_eq!(loaded.roots[3], building.roots[0]);
+    assert_eq!(loaded.blocks.iter().filter(|b| b.root == 0).count(), 10);
+    assert_eq!(loaded.blocks.iter().filter(|b| b.root == 1).count(), 10);
+
+    building.blocks.clear();
+    let mut buffer = Vec::new();
+    buffer.write_building(&building, 6).unwrap();
+    assert_eq!((&buffer[..]).read_building().unwrap().roots, building.roots);
 }
@@ -27,11 +27,37 @@
 //!
 //! // Serialize it
 //! let mut buffer = vec![];
-//! building.write_building(&mut buffer, version).unwrap();
+//! buffer.write_building(&building, version).unwrap();
 //!
 //! // Deserialize it
-//! let loaded = Building::read_building(&buffer[..]).unwrap();
+//! let loaded = (&buffer[..]).read_building().unwrap();
 //! ```
+//!
+//! ## Serde
+//!
+//! With the `serde` feature, the structs in [`structs`] implement `Serialize` and
+//! `Deserialize`. The representation is stable:
+//! - Structs are maps with the Rust field names as keys.
+//! - [`catalog::BlockKind`] is the plain numeric id.
+//! - Block colors are `[r, g, b, a]` arrays of `0..=255`, metadata colors are
+//!   `[r, g, b, a]` arrays of floats. Missing block colors are `null`.
+//! - [`structs::TypeSettings`] is tagged with a snake case `type` and its fields
+//!   in `data`: `{"type": "none"}`, `{"type": "math_block", "data": {"function": ...}}`
+//!   and `{"type": "raw", "data": [1, 2, 3]}`.
+//! - `Building::trailing` is omitted when empty.
 
 pub mod structs;
-pub mod io;
\ No newline at end of file
+pub mod catalog;
+pub mod math;
+pub mod sim;
+pub mod graph;
+pub mod roots;
+pub mod merge;
+pub mod diff;
+pub mod patch;
+pub mod spatial;
+pub mod rotation;
+pub mod transform;
+pub mod io;
+pub mod text;
+pub mod validation;
\ No newline at end of file
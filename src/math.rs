@@ -0,0 +1,510 @@
+//! Parsing and evaluation of math block expressions.
+//!
+//! [`TypeSettings::MathBlock`] stores its
+//! expression as a string. [`parse`] turns it into an [`Expr`], which can report
+//! the input slots it uses and be evaluated against input values.
+//!
+//! The grammar follows what the expressions in existing files look like, it has
+//! not been checked against the game's own parser:
+//! - numbers like `2`, `0.5` or `.5`
+//! - inputs as single lowercase letters, `a` is slot 0, `b` is slot 1 and so on
+//! - `+`, `-`, `*`, `/`, `%` and `^` (right associative, binds tighter than unary `-`)
+//! - parentheses and the functions listed in [`Function`]
+//!
+//! ```rust
+//! use sw_structure_io::math::parse;
+//!
+//! let expr = parse("max(a, b) * 2").unwrap();
+//! assert_eq!(expr.eval(&[1.0, 3.0]), 6.0);
+//! ```
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use thiserror::Error;
+
+use crate::structs::{Building, TypeSettings};
+use crate::validation::Diagnostic;
+
+/// A parsed expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(f32),
+    /// The value of an input slot.
+    Input(u8),
+    Neg(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+}
+
+/// Functions that can be called in an expression.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Function {
+    Abs,
+    Sqrt,
+    Sin,
+    Cos,
+    Tan,
+    Floor,
+    Ceil,
+    Round,
+    Min,
+    Max,
+}
+
+impl Function {
+    const ALL: [Function; 10] = [
+        Function::Abs, Function::Sqrt, Function::Sin, Function::Cos, Function::Tan,
+        Function::Floor, Function::Ceil, Function::Round, Function::Min, Function::Max,
+    ];
+
+    /// Name of the function in expressions.
+    pub fn name(self) -> &'static str {
+        match self {
+            Function::Abs => "abs",
+            Function::Sqrt => "sqrt",
+            Function::Sin => "sin",
+            Function::Cos => "cos",
+            Function::Tan => "tan",
+            Function::Floor => "floor",
+            Function::Ceil => "ceil",
+            Function::Round => "round",
+            Function::Min => "min",
+            Function::Max => "max",
+        }
+    }
+
+    /// Number of arguments the function takes.
+    pub fn arity(self) -> usize {
+        match self {
+            Function::Min | Function::Max => 2,
+            _ => 1,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Function> {
+        Function::ALL.into_iter().find(|f| f.name() == name)
+    }
+}
+
+/// Why an expression couldn't be parsed. Offsets are byte offsets into the expression.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum ParseError {
+    #[error("the expression is empty")]
+    Empty,
+
+    #[error("unexpected character {ch:?} at {offset}")]
+    UnexpectedChar { ch: char, offset: usize },
+
+    #[error("unexpected {found} at {offset}")]
+    UnexpectedToken { found: String, offset: usize },
+
+    #[error("the expression ends unexpectedly")]
+    UnexpectedEnd,
+
+    #[error("unknown identifier {name:?} at {offset}")]
+    UnknownIdentifier { name: String, offset: usize },
+
+    #[error("{name} takes {expected} arguments but {found} were given at {offset}")]
+    ArgumentCount { name: &'static str, expected: usize, found: usize, offset: usize },
+
+    #[error("the expression nests deeper than {max} levels at {offset}")]
+    TooDeep { max: usize, offset: usize },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f32),
+    Ident(String),
+    Op(char),
+    Open,
+    Close,
+    Comma,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "number {n}"),
+            Token::Ident(name) => write!(f, "{name:?}"),
+            Token::Op(op) => write!(f, "'{op}'"),
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+            Token::Comma => write!(f, "','"),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+
+    while let Some(&(offset, ch)) = chars.peek() {
+        let token = match ch {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut end = offset;
+                while let Some(&(i, c)) = chars.peek() && (c.is_ascii_digit() || c == '.') {
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                let text = &source[offset..end];
+                let value = text.parse().map_err(|_| ParseError::UnexpectedToken {
+                    found: format!("{text:?}"),
+                    offset,
+                })?;
+                tokens.push((Token::Number(value), offset));
+                continue;
+            }
+            c if c.is_ascii_alphabetic() => {
+                let mut end = offset;
+                while let Some(&(i, c)) = chars.peek() && c.is_ascii_alphanumeric() {
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                tokens.push((Token::Ident(source[offset..end].to_string()), offset));
+                continue;
+            }
+            '+' | '-' | '*' | '/' | '%' | '^' => Token::Op(ch),
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ',' => Token::Comma,
+            ch => return Err(ParseError::UnexpectedChar { ch, offset }),
+        };
+        tokens.push((token, offset));
+        chars.next();
+    }
+
+    Ok(tokens)
+}
+
+/// How deep parentheses, function calls and prefix operators may nest. The parser
+/// recurses for every level, so this bounds its stack usage.
+const MAX_DEPTH: usize = 256;
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    next: usize,
+    depth: usize,
+}
+
+impl Parser {
+    /// Runs `f` one nesting level deeper.
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        if self.depth == MAX_DEPTH {
+            let offset = self.tokens.get(self.next).map_or(0, |&(_, offset)| offset);
+            return Err(ParseError::TooDeep { max: MAX_DEPTH, offset });
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(t, _)| t)
+    }
+
+    fn bump(&mut self) -> Result<(Token, usize), ParseError> {
+        let token = self.tokens.get(self.next).cloned().ok_or(ParseError::UnexpectedEnd)?;
+        self.next += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ParseError> {
+        match self.bump()? {
+            (token, _) if token == expected => Ok(()),
+            (token, offset) => Err(ParseError::UnexpectedToken { found: token.to_string(), offset }),
+        }
+    }
+
+    /// Parses binary operators binding at least as tight as `min`.
+    fn expr(&mut self, min: u8) -> Result<Expr, ParseError> {
+        let mut lhs = self.unary()?;
+
+        while let Some(&Token::Op(op)) = self.peek() {
+            let (op, precedence) = match op {
+                '+' => (BinaryOp::Add, 1),
+                '-' => (BinaryOp::Sub, 1),
+                '*' => (BinaryOp::Mul, 2),
+                '/' => (BinaryOp::Div, 2),
+                '%' => (BinaryOp::Rem, 2),
+                _ => (BinaryOp::Pow, 4),
+            };
+            if precedence < min {
+                break;
+            }
+            self.next += 1;
+            // `^` is right associative, everything else left associative.
+            let rhs = if op == BinaryOp::Pow { self.power_rhs()? } else { self.expr(precedence + 1)? };
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        self.nested(|p| {
+            if p.peek() == Some(&Token::Op('-')) {
+                p.next += 1;
+                // Unary minus binds looser than `^`: `-a^2` is `-(a^2)`.
+                let operand = p.expr(3)?;
+                return Ok(Expr::Neg(Box::new(operand)));
+            }
+            p.primary()
+        })
+    }
+
+    fn power_rhs(&mut self) -> Result<Expr, ParseError> {
+        self.nested(|p| {
+            if p.peek() == Some(&Token::Op('-')) {
+                p.next += 1;
+                return Ok(Expr::Neg(Box::new(p.power_rhs()?)));
+            }
+            let base = p.primary()?;
+            if p.peek() == Some(&Token::Op('^')) {
+                p.next += 1;
+                return Ok(Expr::Binary(BinaryOp::Pow, Box::new(base), Box::new(p.power_rhs()?)));
+            }
+            Ok(base)
+        })
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        self.nested(|p| {
+            match p.bump()? {
+                (Token::Number(value), _) => Ok(Expr::Number(value)),
+                (Token::Open, _) => {
+                    let expr = p.expr(1)?;
+                    p.expect(Token::Close)?;
+                    Ok(expr)
+                }
+                (Token::Ident(name), offset) if p.peek() == Some(&Token::Open) => {
+                    let function = Function::from_name(&name)
+                        .ok_or(ParseError::UnknownIdentifier { name, offset })?;
+                    p.next += 1;
+
+                    let mut args = Vec::new();
+                    if p.peek() != Some(&Token::Close) {
+                        args.push(p.expr(1)?);
+                        while p.peek() == Some(&Token::Comma) {
+                            p.next += 1;
+                            args.push(p.expr(1)?);
+                        }
+                    }
+                    p.expect(Token::Close)?;
+
+                    if args.len() != function.arity() {
+                        return Err(ParseError::ArgumentCount {
+                            name: function.name(),
+                            expected: function.arity(),
+                            found: args.len(),
+                            offset,
+                        });
+                    }
+                    Ok(Expr::Call(function, args))
+                }
+                (Token::Ident(name), offset) => match name.as_bytes() {
+                    &[c @ b'a'..=b'z'] => Ok(Expr::Input(c - b'a')),
+                    _ => Err(ParseError::UnknownIdentifier { name, offset }),
+                },
+                (token, offset) => Err(ParseError::UnexpectedToken { found: token.to_string(), offset }),
+            }
+        })
+    }
+}
+
+/// Parses a math block expression.
+///
+/// # Errors
+/// Returns a [`ParseError`] describing the first problem found.
+pub fn parse(source: &str) -> Result<Expr, ParseError> {
+    let tokens = tokenize(source)?;
+    if tokens.is_empty() {
+        return Err(ParseError::Empty);
+    }
+
+    let mut parser = Parser { tokens, next: 0, depth: 0 };
+    let expr = parser.expr(1)?;
+    match parser.bump() {
+        Err(_) => Ok(expr),
+        Ok((token, offset)) => Err(ParseError::UnexpectedToken { found: token.to_string(), offset }),
+    }
+}
+
+impl Expr {
+    /// Returns the input slots the expression reads.
+    pub fn inputs(&self) -> BTreeSet<u8> {
+        let mut inputs = BTreeSet::new();
+        self.collect_inputs(&mut inputs);
+        inputs
+    }
+
+    fn collect_inputs(&self, inputs: &mut BTreeSet<u8>) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Input(slot) => {
+                inputs.insert(*slot);
+            }
+            Expr::Neg(expr) => expr.collect_inputs(inputs),
+            Expr::Binary(_, lhs, rhs) => {
+                lhs.collect_inputs(inputs);
+                rhs.collect_inputs(inputs);
+            }
+            Expr::Call(_, args) => args.iter().for_each(|arg| arg.collect_inputs(inputs)),
+        }
+    }
+
+    /// Evaluates the expression, `inputs[i]` being the value of slot `i`.
+    ///
+    /// Slots beyond `inputs` read as `0.0`, like an input without a connection.
+    /// Arithmetic follows `f32`, so division by zero gives an infinity or NaN.
+    pub fn eval(&self, inputs: &[f32]) -> f32 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Input(slot) => inputs.get(*slot as usize).copied().unwrap_or(0.0),
+            Expr::Neg(expr) => -expr.eval(inputs),
+            Expr::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(inputs), rhs.eval(inputs));
+                match op {
+                    BinaryOp::Add => lhs + rhs,
+                    BinaryOp::Sub => lhs - rhs,
+                    BinaryOp::Mul => lhs * rhs,
+                    BinaryOp::Div => lhs / rhs,
+                    BinaryOp::Rem => lhs % rhs,
+                    BinaryOp::Pow => lhs.powf(rhs),
+                }
+            }
+            Expr::Call(function, args) => {
+                let arg = |i: usize| args[i].eval(inputs);
+                match function {
+                    Function::Abs => arg(0).abs(),
+                    Function::Sqrt => arg(0).sqrt(),
+                    Function::Sin => arg(0).sin(),
+                    Function::Cos => arg(0).cos(),
+                    Function::Tan => arg(0).tan(),
+                    Function::Floor => arg(0).floor(),
+                    Function::Ceil => arg(0).ceil(),
+                    Function::Round => arg(0).round(),
+                    Function::Min => arg(0).min(arg(1)),
+                    Function::Max => arg(0).max(arg(1)),
+                }
+            }
+        }
+    }
+}
+
+/// Compares the slots an expression reads with the slots a math block assigns.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlotReport {
+    /// Slots the expression reads.
+    pub referenced: BTreeSet<u8>,
+
+    /// Slots that have a connection assigned (the `slots` vector).
+    pub supplied: BTreeSet<u8>,
+}
+
+impl SlotReport {
+    pub fn new(expr: &Expr, slots: &[u8]) -> Self {
+        Self {
+            referenced: expr.inputs(),
+            supplied: slots.iter().copied().collect(),
+        }
+    }
+
+    /// Slots the expression reads but no connection is assigned to.
+    pub fn missing(&self) -> Vec<u8> {
+        self.referenced.difference(&self.supplied).copied().collect()
+    }
+
+    /// Slots with a connection that the expression never reads.
+    pub fn unused(&self) -> Vec<u8> {
+        self.supplied.difference(&self.referenced).copied().collect()
+    }
+}
+
+impl Building {
+    /// Parses the function of every math block and reports the ones that don't
+    /// parse as [`Diagnostic::MathBlockSyntax`].
+    ///
+    /// The grammar is reconstructed from existing files (see the [module](self)
+    /// documentation), so the game may accept functions reported here. Unlike
+    /// [`Building::validate`], this check is advisory and never blocks writing.
+    pub fn lint_math(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            // An empty function means no expression was entered yet.
+            if let Some(metadata) = &block.metadata
+                && let TypeSettings::MathBlock { function, .. } = &metadata.type_settings
+                && !function.trim().is_empty()
+                && let Err(error) = parse(function)
+            {
+                diagnostics.push(Diagnostic::MathBlockSyntax { block: index, error });
+            }
+        }
+
+        diagnostics
+    }
+}
+
+#[test]
+fn test_parse_eval() {
+    use crate::catalog::BlockKind;
+    use crate::structs::*;
+
+    let expr = parse("a + b * 2").unwrap();
+    assert_eq!(expr.eval(&[1.0, 3.0]), 7.0);
+    assert_eq!(expr.inputs(), BTreeSet::from([0, 1]));
+
+    assert_eq!(parse("-2^2").unwrap().eval(&[]), -4.0);
+    assert_eq!(parse("2^3^2").unwrap().eval(&[]), 512.0);
+    assert_eq!(parse("(1 - 2) - 3").unwrap().eval(&[]), -4.0);
+    assert_eq!(parse("min(a, .5) + abs(-c)").unwrap().eval(&[2.0, 0.0, -1.0]), 1.5);
+    assert_eq!(parse("c").unwrap().eval(&[1.0]), 0.0);
+
+    assert_eq!(parse(""), Err(ParseError::Empty));
+    assert_eq!(parse("a +"), Err(ParseError::UnexpectedEnd));
+    assert_eq!(parse("a # b"), Err(ParseError::UnexpectedChar { ch: '#', offset: 2 }));
+    assert_eq!(parse("ab"), Err(ParseError::UnknownIdentifier { name: "ab".to_string(), offset: 0 }));
+    assert_eq!(parse("a b"), Err(ParseError::UnexpectedToken { found: "\"b\"".to_string(), offset: 2 }));
+    assert!(matches!(parse("max(a)"), Err(ParseError::ArgumentCount { expected: 2, found: 1, .. })));
+    assert!(matches!(parse(&"(".repeat(100_000)), Err(ParseError::TooDeep { .. })));
+    assert!(matches!(parse(&format!("2^{}a", "-".repeat(100_000))), Err(ParseError::TooDeep { .. })));
+
+    let mut building = Building::default();
+    building.roots.push(Root::default());
+    for function in ["a +", "", "a + b"] {
+        building.blocks.push(Block {
+            id: BlockKind::MATH_BLOCK,
+            metadata: Some(Metadata {
+                type_settings: TypeSettings::MathBlock {
+                    function: function.to_string(),
+                    incoming_connections_order: vec![],
+                    slots: vec![],
+                },
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+    }
+    assert!(building.validate().is_empty());
+    assert_eq!(building.lint_math(), vec![Diagnostic::MathBlockSyntax { block: 0, error: ParseError::UnexpectedEnd }]);
+
+    let report = SlotReport::new(&parse("a + c").unwrap(), &[0, 1]);
+    assert_eq!(report.missing(), vec![2]);
+    assert_eq!(report.unused(), vec![1]);
+}
@@ -0,0 +1,198 @@
+//! Combining buildings and extracting parts of them.
+
+use std::fmt;
+
+use crate::graph::IndexOverflow;
+use crate::structs::{Building, TypeSettings};
+use crate::transform::Transform;
+
+/// A reference that pointed outside of the selection passed to [`Building::split`].
+///
+/// Indices refer to the original building.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dangling {
+    Connection {
+        block: usize,
+        target: usize,
+    },
+    Load {
+        block: usize,
+        target: usize,
+    },
+    MathInput {
+        block: usize,
+        source: usize,
+    },
+}
+
+impl fmt::Display for Dangling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dangling::Connection { block, target } => {
+                write!(f, "connection from block {block} to unselected block {target} dropped")
+            }
+            Dangling::Load { block, target } => {
+                write!(f, "load of block {block} on unselected block {target} dropped")
+            }
+            Dangling::MathInput { block, source } => {
+                write!(f, "math block {block} input from unselected block {source} dropped")
+            }
+        }
+    }
+}
+
+/// A part of a building extracted by [`Building::split`].
+#[derive(Clone, Debug)]
+pub struct Split {
+    /// The selected blocks and their roots as a standalone building.
+    pub building: Building,
+
+    /// Original index of every block of `building`.
+    pub blocks: Vec<usize>,
+
+    /// Original index of every root of `building`.
+    pub roots: Vec<u16>,
+
+    /// References to blocks outside of the selection, which were dropped.
+    pub dangling: Vec<Dangling>,
+}
+
+impl Building {
+    /// Appends the roots and blocks of `other`, moved by `offset`.
+    ///
+    /// Root indices, connections, loads and math block inputs of the appended
+    /// blocks are re-based onto their new indices. Returns the index of the first
+    /// appended block. `trailing` of `other` is discarded.
+    ///
+    /// # Errors
+    /// Returns [`IndexOverflow`] if a re-based index doesn't fit its integer type
+    /// (`u16` for roots and blocks, `u8` for math block inputs). `self` is left
+    /// unchanged in that case.
+    pub fn merge(&mut self, mut other: Building, offset: [f32; 3]) -> Result<usize, IndexOverflow> {
+        let first_root = self.roots.len();
+        let first_block = self.blocks.len();
+
+        other.transform(&Transform::Translate(offset));
+        for block in other.blocks.iter_mut() {
+            let root = block.root as usize + first_root;
+            block.root = u16::try_from(root).map_err(|_| IndexOverflow::Root(root))?;
+            block.remap_references(|i| Some(i + first_block))?;
+        }
+
+        self.roots.append(&mut other.roots);
+        self.blocks.append(&mut other.blocks);
+        Ok(first_block)
+    }
+
+    /// Extracts the given blocks and the roots they are attached to into a new
+    /// building.
+    ///
+    /// Blocks and roots keep their relative order. References to blocks outside
+    /// of the selection are dropped and reported in [`Split::dangling`]. `self`
+    /// is left unchanged, use [`Building::remove_block`] to cut the blocks out.
+    ///
+    /// # Panics
+    /// Panics if any of the blocks doesn't exist.
+    pub fn split(&self, blocks: &[usize]) -> Split {
+        let mut selected = blocks.to_vec();
+        selected.sort_unstable();
+        selected.dedup();
+
+        let mut roots: Vec<u16> = selected.iter().map(|&i| self.blocks[i].root).collect();
+        roots.sort_unstable();
+        roots.dedup();
+
+        let mut new_index = vec![None; self.blocks.len()];
+        for (new, &old) in selected.iter().enumerate() {
+            new_index[old] = Some(new);
+        }
+
+        let mut dangling = Vec::new();
+        let mut building = Building {
+            roots: roots.iter().filter_map(|&r| self.roots.get(r as usize).cloned()).collect(),
+            ..Default::default()
+        };
+
+        for &index in selected.iter() {
+            let mut block = self.blocks[index].clone();
+            // Roots that don't exist are kept out of range, like in the original.
+            block.root = match roots.binary_search(&block.root) {
+                Ok(root) if (block.root as usize) < self.roots.len() => root as u16,
+                _ => building.roots.len() as u16,
+            };
+
+            let outside = |i: usize| new_index.get(i).copied().flatten().is_none();
+            for &c in block.connections.iter().filter(|&&c| outside(c as usize)) {
+                dangling.push(Dangling::Connection { block: index, target: c as usize });
+            }
+            if let Some(load) = block.load.filter(|&l| outside(l as usize)) {
+                dangling.push(Dangling::Load { block: index, target: load as usize });
+            }
+            if let Some(metadata) = &block.metadata
+                && let TypeSettings::MathBlock { incoming_connections_order, .. } = &metadata.type_settings
+            {
+                for &source in incoming_connections_order.iter().filter(|&&s| outside(s as usize)) {
+                    dangling.push(Dangling::MathInput { block: index, source: source as usize });
+                }
+            }
+
+            block.remap_references(|i| new_index.get(i).copied().flatten())
+                .expect("references only move to lower indices");
+            building.blocks.push(block);
+        }
+
+        Split {
+            building,
+            blocks: selected,
+            roots,
+            dangling,
+        }
+    }
+}
+
+#[test]
+fn test_merge_split() {
+    use crate::catalog::BlockKind;
+    use crate::structs::*;
+
+    let mut module = Building::default();
+    module.roots.push(Root::default());
+    module.roots.push(Root::default());
+    module.blocks.push(Block { connections: vec![1], load: Some(2), ..Default::default() });
+    module.blocks.push(Block {
+        id: BlockKind::MATH_BLOCK,
+        metadata: Some(Metadata {
+            type_settings: TypeSettings::MathBlock {
+                function: "a".to_string(),
+                incoming_connections_order: vec![0],
+                slots: vec![0],
+            },
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    module.blocks.push(Block { root: 1, position: [0.0, 1.0, 0.0], ..Default::default() });
+
+    let mut building = module.clone();
+    assert_eq!(building.merge(module.clone(), [10.0, 0.0, 0.0]), Ok(3));
+    assert_eq!(building.roots.len(), 4);
+    assert_eq!(building.blocks[3].connections, vec![4]);
+    assert_eq!(building.blocks[3].load, Some(5));
+    assert_eq!(building.blocks[5].root, 3);
+    assert_eq!(building.blocks[5].position, [10.0, 1.0, 0.0]);
+    assert!(building.validate().is_empty());
+
+    let split = building.split(&[5, 3]);
+    assert_eq!(split.blocks, vec![3, 5]);
+    assert_eq!(split.roots, vec![2, 3]);
+    assert_eq!(split.building.blocks[0].load, Some(1));
+    assert_eq!(split.building.blocks[1].root, 1);
+    assert_eq!(split.dangling, vec![Dangling::Connection { block: 3, target: 4 }]);
+    assert!(split.building.blocks[0].connections.is_empty());
+    assert!(split.building.validate().is_empty());
+
+    // Root indices of the appended blocks have to fit into a u16.
+    let mut full = Building { roots: vec![Root::default(); 0x10000], ..Default::default() };
+    assert_eq!(full.merge(module, [0.0; 3]), Err(IndexOverflow::Root(0x10000)));
+    assert!(full.blocks.is_empty() && full.roots.len() == 0x10000);
+}
@@ -0,0 +1,680 @@
+//! Patches: differences that can be applied to other buildings.
+//!
+//! A [`Patch`] is made from two revisions of a building, like a [`Diff`], and can
+//! then be applied to any building that contains the changed blocks, e.g. the
+//! same wiring fix to several variants of a vehicle.
+//!
+//! Blocks are identified by an [`Anchor`]: their index, id and position in the
+//! building the patch was made from. Applying a patch finds every anchored block
+//! in the target, at the same index or, if indices shifted, as the only block
+//! with the same id and position. References in connections, loads and math
+//! block inputs are re-based onto the target's indices.
+//!
+//! Every change records the value it expects to replace. If an anchored block
+//! can't be found or a value differs, applying the patch fails with the list of
+//! [`Conflict`]s and leaves the target unchanged.
+//!
+//! ```rust
+//! use sw_structure_io::diff::Matching;
+//! use sw_structure_io::patch::Patch;
+//! use sw_structure_io::structs::*;
+//!
+//! let mut old = Building::default();
+//! old.roots.push(Root::default());
+//! old.blocks.push(Block::default());
+//! old.blocks.push(Block { position: [1.0, 0.0, 0.0], ..Default::default() });
+//!
+//! let mut new = old.clone();
+//! new.connect(0, 1);
+//! let patch = Patch::new(&old, &new, Matching::Index).unwrap();
+//!
+//! // A variant with an extra block in front.
+//! let mut variant = old.clone();
+//! variant.insert_block(0, Block { position: [5.0, 0.0, 0.0], ..Default::default() }).unwrap();
+//!
+//! patch.apply(&mut variant).unwrap();
+//! assert_eq!(variant.blocks[1].connections, vec![2]);
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::catalog::BlockKind;
+use crate::diff::{BlockChange, Diff, FieldChange, Matching, RootChange};
+use crate::graph::{block_u16, IndexOverflow};
+use crate::structs::{Block, Building, TypeSettings};
+
+/// A block of the building a patch was made from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Anchor {
+    pub index: usize,
+    pub id: BlockKind,
+    pub position: [f32; 3],
+}
+
+impl Anchor {
+    fn of(building: &Building, index: usize) -> Anchor {
+        let block = &building.blocks[index];
+        Anchor { index, id: block.id, position: block.position }
+    }
+
+    fn matches(&self, block: &Block) -> bool {
+        block.id == self.id && block.position.map(f32::to_bits) == self.position.map(f32::to_bits)
+    }
+}
+
+/// A block referenced by a patch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlockRef {
+    /// A block that exists before the patch is applied.
+    Existing(Anchor),
+
+    /// The n-th block added by the patch.
+    Added(usize),
+}
+
+/// A change of a block, see [`BlockPatch::Modify`].
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Edit {
+    /// A change of a field that doesn't reference blocks.
+    Field(FieldChange),
+    Connect(BlockRef),
+    Disconnect(Anchor),
+    Load { from: Option<Anchor>, to: Option<BlockRef> },
+
+    /// Math block inputs as `(source block, slot)` pairs.
+    MathInputs { from: Vec<(Anchor, u8)>, to: Vec<(BlockRef, u8)> },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlockPatch {
+    /// Appends a block. `block` has no references, they are set from the other
+    /// fields.
+    Add {
+        block: Box<Block>,
+        connections: Vec<BlockRef>,
+        load: Option<BlockRef>,
+        math_inputs: Vec<(BlockRef, u8)>,
+    },
+    Remove(Anchor),
+    Modify { block: Anchor, edits: Vec<Edit> },
+}
+
+/// Changes that can be applied to any building containing the changed blocks.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Patch {
+    /// Number of roots of the building the patch was made from. Roots at or
+    /// beyond this index are added by the patch.
+    pub root_count: u16,
+
+    /// Root changes by index, like in [`Diff::roots`].
+    pub roots: Vec<RootChange>,
+
+    pub blocks: Vec<BlockPatch>,
+}
+
+/// Why a patch couldn't be applied. Block indices refer to the building the
+/// patch was made from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Conflict {
+    /// No block with the anchor's id and position.
+    MissingBlock { block: usize },
+
+    /// Several blocks with the anchor's id and position, or several anchors for
+    /// the same block.
+    AmbiguousBlock { block: usize, candidates: Vec<usize> },
+
+    /// A field doesn't have the value the patch expects.
+    Mismatch { block: usize, field: &'static str },
+
+    /// A field of the `n`-th added block (see [`BlockRef::Added`]) can't be set,
+    /// e.g. math inputs of a block that isn't a math block.
+    AddedBlock { n: usize, field: &'static str },
+
+    /// A re-based index doesn't fit its integer type.
+    Overflow(IndexOverflow),
+
+    /// A root is missing or doesn't have the value the patch expects.
+    RootMismatch { root: u16 },
+
+    /// A root to remove still has blocks.
+    RootInUse { root: u16 },
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::MissingBlock { block } => write!(f, "block {block} not found"),
+            Conflict::AmbiguousBlock { block, candidates } => {
+                write!(f, "block {block} is ambiguous, candidates {candidates:?}")
+            }
+            Conflict::Mismatch { block, field } => write!(f, "{field} of block {block} doesn't match"),
+            Conflict::AddedBlock { n, field } => write!(f, "{field} of added block {n} can't be set"),
+            Conflict::Overflow(overflow) => write!(f, "{overflow}"),
+            Conflict::RootMismatch { root } => write!(f, "root {root} doesn't match"),
+            Conflict::RootInUse { root } => write!(f, "root {root} still has blocks"),
+        }
+    }
+}
+
+impl Patch {
+    /// Makes a patch that turns `old` into `new`.
+    ///
+    /// # Errors
+    /// Returns [`IndexOverflow::Root`] if `old` has more roots than a `u16` can
+    /// count.
+    ///
+    /// # Panics
+    /// Panics if a reference in either building points to a block that doesn't
+    /// exist (see [`Building::validate`]).
+    pub fn new(old: &Building, new: &Building, matching: Matching) -> Result<Patch, IndexOverflow> {
+        Patch::from_diff(old, &old.diff(new, matching))
+    }
+
+    /// Makes a patch from a diff of `old` to another building.
+    ///
+    /// # Errors
+    /// Returns [`IndexOverflow::Root`] if `old` has more roots than a `u16` can
+    /// count.
+    ///
+    /// # Panics
+    /// Panics if `diff` wasn't made from `old`, or on references to blocks that
+    /// don't exist.
+    pub fn from_diff(old: &Building, diff: &Diff) -> Result<Patch, IndexOverflow> {
+        let root_count = u16::try_from(old.roots.len()).map_err(|_| IndexOverflow::Root(old.roots.len()))?;
+        let anchor = |index: usize| Anchor::of(old, index);
+
+        // Every block of the new building as a patch reference.
+        let mut new_ref = HashMap::new();
+        for (index, matched) in diff.matches.iter().enumerate() {
+            if let Some(matched) = *matched {
+                new_ref.insert(matched, BlockRef::Existing(anchor(index)));
+            }
+        }
+        let added = diff.blocks.iter().filter_map(|c| match c {
+            BlockChange::Added { index, .. } => Some(*index),
+            _ => None,
+        });
+        for (n, index) in added.enumerate() {
+            new_ref.insert(index, BlockRef::Added(n));
+        }
+        let new_ref = |index: usize| *new_ref.get(&index).expect("reference to a block that doesn't exist");
+
+        let blocks = diff
+            .blocks
+            .iter()
+            .map(|change| match change {
+                BlockChange::Added { block, .. } => {
+                    let mut block = Box::new(block.clone());
+                    let connections = block.connections.drain(..).map(|c| new_ref(c as usize)).collect();
+                    let load = block.load.take().map(|l| new_ref(l as usize));
+                    let mut math_inputs = Vec::new();
+                    if let Some(metadata) = &mut block.metadata
+                        && let TypeSettings::MathBlock { incoming_connections_order, slots, .. } = &mut metadata.type_settings
+                    {
+                        math_inputs = incoming_connections_order
+                            .drain(..)
+                            .zip(slots.drain(..))
+                            .map(|(source, slot)| (new_ref(source as usize), slot))
+                            .collect();
+                    }
+                    BlockPatch::Add { block, connections, load, math_inputs }
+                }
+                BlockChange::Removed { index, .. } => BlockPatch::Remove(anchor(*index)),
+                BlockChange::Modified { old, changes, .. } => BlockPatch::Modify {
+                    block: anchor(*old),
+                    edits: changes
+                        .iter()
+                        .map(|change| match change {
+                            FieldChange::ConnectionAdded { target } => Edit::Connect(new_ref(*target)),
+                            FieldChange::ConnectionRemoved { target } => Edit::Disconnect(anchor(*target)),
+                            FieldChange::Load { from, to } => Edit::Load {
+                                from: from.map(anchor),
+                                to: to.map(new_ref),
+                            },
+                            FieldChange::MathInputs { from, to } => Edit::MathInputs {
+                                from: from.iter().map(|&(source, slot)| (anchor(source), slot)).collect(),
+                                to: to.iter().map(|&(source, slot)| (new_ref(source), slot)).collect(),
+                            },
+                            change => Edit::Field(change.clone()),
+                        })
+                        .collect(),
+                },
+            })
+            .collect();
+
+        Ok(Patch {
+            root_count,
+            roots: diff.roots.clone(),
+            blocks,
+        })
+    }
+
+    /// Returns `true` if the patch doesn't change anything.
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty() && self.blocks.is_empty()
+    }
+
+    fn anchors(&self) -> Vec<&Anchor> {
+        fn existing(r: &BlockRef) -> Option<&Anchor> {
+            match r {
+                BlockRef::Existing(anchor) => Some(anchor),
+                BlockRef::Added(_) => None,
+            }
+        }
+
+        let mut anchors: Vec<&Anchor> = Vec::new();
+        for patch in self.blocks.iter() {
+            match patch {
+                BlockPatch::Add { connections, load, math_inputs, .. } => {
+                    anchors.extend(connections.iter().filter_map(existing));
+                    anchors.extend(load.iter().filter_map(existing));
+                    anchors.extend(math_inputs.iter().filter_map(|(r, _)| existing(r)));
+                }
+                BlockPatch::Remove(anchor) => anchors.push(anchor),
+                BlockPatch::Modify { block, edits } => {
+                    anchors.push(block);
+                    for edit in edits {
+                        match edit {
+                            Edit::Field(_) => {}
+                            Edit::Connect(to) => anchors.extend(existing(to)),
+                            Edit::Disconnect(anchor) => anchors.push(anchor),
+                            Edit::Load { from, to } => {
+                                anchors.extend(from.iter());
+                                anchors.extend(to.iter().filter_map(existing));
+                            }
+                            Edit::MathInputs { from, to } => {
+                                anchors.extend(from.iter().map(|(a, _)| a));
+                                anchors.extend(to.iter().filter_map(|(r, _)| existing(r)));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        anchors
+    }
+
+    /// Applies the patch to `target`.
+    ///
+    /// Added roots and blocks are appended. Removed blocks are removed last, with
+    /// [`Building::remove_block`].
+    ///
+    /// # Errors
+    /// Returns every conflict found, including re-based indices that don't fit
+    /// their integer type (`u16` for roots and blocks, `u8` for math block inputs).
+    /// `target` is only changed if there are none.
+    pub fn apply(&self, target: &mut Building) -> Result<(), Vec<Conflict>> {
+        let mut conflicts = Vec::new();
+
+        let mut resolved: HashMap<usize, usize> = HashMap::new();
+        let mut resolved_by: HashMap<usize, usize> = HashMap::new();
+        let mut seen = HashSet::new();
+        for anchor in self.anchors() {
+            if !seen.insert(anchor.index) {
+                continue;
+            }
+            let candidates: Vec<usize> = match target.blocks.get(anchor.index) {
+                Some(block) if anchor.matches(block) => vec![anchor.index],
+                _ => (0..target.blocks.len()).filter(|&i| anchor.matches(&target.blocks[i])).collect(),
+            };
+            match candidates[..] {
+                [] => conflicts.push(Conflict::MissingBlock { block: anchor.index }),
+                [index] => match resolved_by.insert(index, anchor.index) {
+                    None => {
+                        resolved.insert(anchor.index, index);
+                    }
+                    Some(other) => conflicts.push(Conflict::AmbiguousBlock { block: other, candidates }),
+                },
+                _ => conflicts.push(Conflict::AmbiguousBlock { block: anchor.index, candidates }),
+            }
+        }
+        if !conflicts.is_empty() {
+            return Err(conflicts);
+        }
+
+        let mut building = target.clone();
+        let first_added = building.blocks.len();
+        let first_added_root = building.roots.len();
+
+        let block_ref = |r: &BlockRef| match *r {
+            BlockRef::Existing(anchor) => resolved[&anchor.index],
+            BlockRef::Added(n) => first_added + n,
+        };
+        let root = |r: u16| match r.checked_sub(self.root_count) {
+            Some(n) => {
+                let index = first_added_root + n as usize;
+                u16::try_from(index).map_err(|_| IndexOverflow::Root(index))
+            }
+            None => Ok(r),
+        };
+
+        let mut removed_roots = Vec::new();
+        for change in self.roots.iter() {
+            match change {
+                RootChange::Added { root, .. } => {
+                    if building.roots.len() > u16::MAX as usize {
+                        conflicts.push(Conflict::Overflow(IndexOverflow::Root(building.roots.len())));
+                    }
+                    building.roots.push(root.clone());
+                }
+                RootChange::Removed { index, root } => {
+                    if building.roots.get(*index as usize) != Some(root) {
+                        conflicts.push(Conflict::RootMismatch { root: *index });
+                    }
+                    removed_roots.push(*index);
+                }
+                RootChange::Modified { index, from, to } => match building.roots.get_mut(*index as usize) {
+                    Some(root) if root == from => *root = to.clone(),
+                    _ => conflicts.push(Conflict::RootMismatch { root: *index }),
+                },
+            }
+        }
+
+        let mut removed_blocks = Vec::new();
+        let mut added = 0;
+        for patch in self.blocks.iter() {
+            match patch {
+                BlockPatch::Add { block, connections, load, math_inputs } => {
+                    let mut block = Block::clone(block);
+                    let result = (|| {
+                        block.root = root(block.root)?;
+                        block.connections = connections.iter().map(|r| block_u16(block_ref(r))).collect::<Result<_, _>>()?;
+                        block.load = load.as_ref().map(|r| block_u16(block_ref(r))).transpose()?;
+                        if !math_inputs.is_empty() {
+                            let resolved_inputs: Vec<_> = math_inputs.iter().map(|(r, slot)| (block_ref(r), *slot)).collect();
+                            set_math_inputs(&mut block, &resolved_inputs)?;
+                        }
+                        Ok(())
+                    })();
+                    match result {
+                        Ok(()) => {}
+                        Err(Failure::Field(field)) => conflicts.push(Conflict::AddedBlock { n: added, field }),
+                        Err(Failure::Overflow(overflow)) => conflicts.push(Conflict::Overflow(overflow)),
+                    }
+                    building.blocks.push(block);
+                    added += 1;
+                }
+                BlockPatch::Remove(anchor) => removed_blocks.push(resolved[&anchor.index]),
+                BlockPatch::Modify { block: anchor, edits } => {
+                    let block = &mut building.blocks[resolved[&anchor.index]];
+                    for edit in edits {
+                        match apply_edit(block, edit, &resolved, &block_ref, &root) {
+                            Ok(()) => {}
+                            Err(Failure::Field(field)) => conflicts.push(Conflict::Mismatch { block: anchor.index, field }),
+                            Err(Failure::Overflow(overflow)) => conflicts.push(Conflict::Overflow(overflow)),
+                        }
+                    }
+                }
+            }
+        }
+
+        removed_blocks.sort_unstable();
+        for &index in removed_blocks.iter().rev() {
+            building.remove_block(index);
+        }
+
+        removed_roots.sort_unstable();
+        for &index in removed_roots.iter().rev() {
+            if building.remove_root(index).is_none() {
+                conflicts.push(Conflict::RootInUse { root: index });
+            }
+        }
+
+        if !conflicts.is_empty() {
+            return Err(conflicts);
+        }
+        *target = building;
+        Ok(())
+    }
+}
+
+/// Why an edit couldn't be applied.
+enum Failure {
+    /// The name of the field that didn't match.
+    Field(&'static str),
+    Overflow(IndexOverflow),
+}
+
+impl From<&'static str> for Failure {
+    fn from(field: &'static str) -> Self {
+        Failure::Field(field)
+    }
+}
+
+impl From<IndexOverflow> for Failure {
+    fn from(overflow: IndexOverflow) -> Self {
+        Failure::Overflow(overflow)
+    }
+}
+
+fn math_inputs(block: &Block) -> Option<Vec<(usize, u8)>> {
+    match &block.metadata.as_ref()?.type_settings {
+        TypeSettings::MathBlock { incoming_connections_order, slots, .. } => Some(
+            incoming_connections_order.iter().map(|&s| s as usize).zip(slots.iter().copied()).collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Fails if the block isn't a math block or an input doesn't fit into a `u8`.
+fn set_math_inputs(block: &mut Block, inputs: &[(usize, u8)]) -> Result<(), Failure> {
+    let Some(metadata) = &mut block.metadata else {
+        return Err("math inputs".into());
+    };
+    let TypeSettings::MathBlock { incoming_connections_order, slots, .. } = &mut metadata.type_settings else {
+        return Err("math inputs".into());
+    };
+    *incoming_connections_order = inputs
+        .iter()
+        .map(|&(source, _)| u8::try_from(source).map_err(|_| IndexOverflow::MathInput(source)))
+        .collect::<Result<_, _>>()?;
+    *slots = inputs.iter().map(|&(_, slot)| slot).collect();
+    Ok(())
+}
+
+/// Applies an edit.
+fn apply_edit(
+    block: &mut Block,
+    edit: &Edit,
+    resolved: &HashMap<usize, usize>,
+    block_ref: &impl Fn(&BlockRef) -> usize,
+    root: &impl Fn(u16) -> Result<u16, IndexOverflow>,
+) -> Result<(), Failure> {
+    match edit {
+        Edit::Field(change) => apply_field(block, change, root),
+        Edit::Connect(to) => {
+            let to = block_u16(block_ref(to))?;
+            if block.connections.contains(&to) {
+                return Err("connections".into());
+            }
+            block.connections.push(to);
+            Ok(())
+        }
+        Edit::Disconnect(anchor) => {
+            let to = resolved[&anchor.index];
+            let position = block.connections.iter().position(|&c| c as usize == to).ok_or("connections")?;
+            block.connections.remove(position);
+            Ok(())
+        }
+        Edit::Load { from, to } => {
+            if block.load.map(usize::from) != from.map(|a| resolved[&a.index]) {
+                return Err("load".into());
+            }
+            block.load = to.as_ref().map(|r| block_u16(block_ref(r))).transpose()?;
+            Ok(())
+        }
+        Edit::MathInputs { from, to } => {
+            let expected: Vec<_> = from.iter().map(|(a, slot)| (resolved[&a.index], *slot)).collect();
+            if math_inputs(block) != Some(expected) {
+                return Err("math inputs".into());
+            }
+            let to: Vec<_> = to.iter().map(|(r, slot)| (block_ref(r), *slot)).collect();
+            set_math_inputs(block, &to)
+        }
+    }
+}
+
+/// Applies a field change.
+fn apply_field(
+    block: &mut Block,
+    change: &FieldChange,
+    root: &impl Fn(u16) -> Result<u16, IndexOverflow>,
+) -> Result<(), Failure> {
+    macro_rules! set {
+        ($field:expr, $name:expr, $from:expr, $to:expr) => {{
+            if $field != *$from {
+                return Err($name.into());
+            }
+            $field = $to.clone();
+        }};
+    }
+    macro_rules! set_metadata {
+        ($field:ident, $from:expr, $to:expr) => {{
+            let metadata = block.metadata.as_mut().ok_or(stringify!($field))?;
+            set!(metadata.$field, stringify!($field), $from, $to);
+        }};
+    }
+
+    match change {
+        FieldChange::Id { from, to } => set!(block.id, "id", from, to),
+        FieldChange::Root { from, to } => set!(block.root, "root", from, root(*to)?),
+        FieldChange::Position { from, to } => set!(block.position, "position", from, to),
+        FieldChange::Rotation { from, to } => set!(block.rotation, "rotation", from, to),
+        FieldChange::Name { from, to } => set!(block.name, "name", from, to),
+        FieldChange::EnableState { from, to } => set!(block.enable_state, "enable state", from, to),
+        FieldChange::EnableStateCurrent { from, to } => {
+            set!(block.enable_state_current, "current enable state", from, to)
+        }
+        FieldChange::Color { from, to } => set!(block.color, "color", from, to),
+        FieldChange::Metadata { from, to } => {
+            if block.metadata.as_ref() != from.as_deref() {
+                return Err("metadata".into());
+            }
+            block.metadata = to.as_deref().cloned();
+        }
+        FieldChange::Toggles { from, to } => set_metadata!(toggles, from, to),
+        FieldChange::Values { from, to } => set_metadata!(values, from, to),
+        FieldChange::Fields { from, to } => set_metadata!(fields, from, to),
+        FieldChange::Dropdowns { from, to } => set_metadata!(dropdowns, from, to),
+        FieldChange::Colors { from, to } => set_metadata!(colors, from, to),
+        FieldChange::Gradients { from, to } => set_metadata!(gradients, from, to),
+        FieldChange::Vectors { from, to } => set_metadata!(vectors, from, to),
+        FieldChange::TypeSettings { from, to } => set_metadata!(type_settings, from, to),
+        FieldChange::MathFunction { from, to } => {
+            let function = match block.metadata.as_mut().map(|m| &mut m.type_settings) {
+                Some(TypeSettings::MathBlock { function, .. }) => function,
+                _ => return Err("function".into()),
+            };
+            set!(*function, "function", from, to);
+        }
+        // References are patched with the other edits.
+        FieldChange::ConnectionAdded { .. } | FieldChange::ConnectionRemoved { .. } => return Err("connections".into()),
+        FieldChange::Load { .. } => return Err("load".into()),
+        FieldChange::MathInputs { .. } => return Err("math inputs".into()),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_patch() {
+    use crate::structs::*;
+
+    let math = |function: &str, inputs: Vec<u8>| Block {
+        id: BlockKind::MATH_BLOCK,
+        metadata: Some(Metadata {
+            type_settings: TypeSettings::MathBlock {
+                function: function.to_string(),
+                slots: (0..inputs.len() as u8).collect(),
+                incoming_connections_order: inputs,
+            },
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let mut old = Building::default();
+    old.roots.push(Root::default());
+    old.blocks.push(Block::default());
+    old.blocks.push(Block { position: [1.0, 0.0, 0.0], ..Default::default() });
+    old.blocks.push(Block { position: [0.0, 1.0, 0.0], ..math("a", vec![0]) });
+    old.connect(0, 2);
+
+    // Rewire the math block from block 0 to block 1 and add a block feeding it.
+    let mut new = old.clone();
+    new.disconnect(0, 2);
+    new.connect(1, 2);
+    new.blocks.push(Block { position: [2.0, 0.0, 0.0], ..Default::default() });
+    new.connect(3, 2);
+    new.blocks[2] = Block { position: [0.0, 1.0, 0.0], ..math("a + b", vec![1, 3]) };
+    let patch = Patch::new(&old, &new, Matching::Index).unwrap();
+
+    let mut same = old.clone();
+    patch.apply(&mut same).unwrap();
+    assert_eq!(same, new);
+
+    // A variant with blocks in another order and an extra one.
+    let mut variant = Building::default();
+    variant.roots.push(Root::default());
+    variant.blocks.push(Block { position: [0.0, 1.0, 0.0], ..math("a", vec![2]) });
+    variant.blocks.push(Block { position: [9.0, 0.0, 0.0], ..Default::default() });
+    variant.blocks.push(Block::default());
+    variant.blocks.push(Block { position: [1.0, 0.0, 0.0], ..Default::default() });
+    variant.connect(2, 0);
+
+    patch.apply(&mut variant).unwrap();
+    assert!(variant.blocks[2].connections.is_empty());
+    assert_eq!(variant.blocks[3].connections, vec![0]);
+    assert_eq!(variant.blocks[4].connections, vec![0]);
+    assert_eq!(math_inputs(&variant.blocks[0]), Some(vec![(3, 0), (4, 1)]));
+    assert!(variant.validate().is_empty());
+
+    // Applying twice conflicts and leaves the building unchanged.
+    let before = variant.clone();
+    let conflicts = patch.apply(&mut variant).unwrap_err();
+    assert!(conflicts.contains(&Conflict::Mismatch { block: 0, field: "connections" }));
+    assert!(conflicts.contains(&Conflict::Mismatch { block: 2, field: "function" }));
+    assert_eq!(variant, before);
+
+    let mut unrelated = Building::default();
+    unrelated.roots.push(Root::default());
+    assert_eq!(patch.apply(&mut unrelated), Err(vec![
+        Conflict::MissingBlock { block: 0 },
+        Conflict::MissingBlock { block: 2 },
+        Conflict::MissingBlock { block: 1 },
+    ]));
+
+    // Added blocks that can't be set up are reported by their position in the patch.
+    let add = |block: Block, math_inputs| BlockPatch::Add {
+        block: Box::new(block),
+        connections: vec![],
+        load: None,
+        math_inputs,
+    };
+    let patch = Patch {
+        root_count: 1,
+        roots: vec![],
+        blocks: vec![
+            add(Block::default(), vec![]),
+            add(Block::default(), vec![(BlockRef::Added(0), 0)]),
+            add(math("a", vec![]), vec![(BlockRef::Added(0), 0)]),
+        ],
+    };
+    assert_eq!(patch.apply(&mut unrelated), Err(vec![Conflict::AddedBlock { n: 1, field: "math inputs" }]));
+
+    unrelated.blocks = vec![Block::default(); 0x100];
+    assert_eq!(patch.apply(&mut unrelated), Err(vec![
+        Conflict::AddedBlock { n: 1, field: "math inputs" },
+        Conflict::Overflow(IndexOverflow::MathInput(0x100)),
+    ]));
+    assert_eq!(unrelated.blocks.len(), 0x100);
+
+    // Roots beyond what a u16 can count are an error, not a panic.
+    unrelated.roots = vec![Root::default(); 0x10000];
+    assert_eq!(Patch::from_diff(&unrelated, &Diff::default()), Err(IndexOverflow::Root(0x10000)));
+}
@@ -0,0 +1,156 @@
+//! Adding, removing and reassigning roots.
+//!
+//! Blocks refer to their root by index through [`Block::root`](crate::structs::Block::root).
+//! Block transforms are stored in world space, so moving a block to another root
+//! never changes its position or rotation, only the index has to be kept valid.
+//! The methods here also keep [`Block::load`](crate::structs::Block::load)
+//! valid, which must always refer to a block of another root.
+
+use crate::structs::{Building, Root};
+
+impl Building {
+    /// Appends a root and returns its index.
+    ///
+    /// # Panics
+    /// Panics if the building already has `u16::MAX + 1` roots.
+    pub fn add_root(&mut self, root: Root) -> u16 {
+        let index = u16::try_from(self.roots.len()).expect("too many roots");
+        self.roots.push(root);
+        index
+    }
+
+    /// Returns the indices of the blocks attached to `root`.
+    pub fn root_blocks(&self, root: u16) -> impl Iterator<Item = usize> + '_ {
+        self.blocks
+            .iter()
+            .enumerate()
+            .filter(move |(_, b)| b.root == root)
+            .map(|(i, _)| i)
+    }
+
+    /// Moves blocks to another root.
+    ///
+    /// Loads that would end up attaching two blocks of the same root are removed,
+    /// the indices of the blocks that lost their load are returned.
+    ///
+    /// # Panics
+    /// Panics if `root` or any of the blocks doesn't exist.
+    pub fn move_blocks(&mut self, blocks: &[usize], root: u16) -> Vec<usize> {
+        assert!((root as usize) < self.roots.len(), "root {root} doesn't exist");
+        for &block in blocks {
+            self.blocks[block].root = root;
+        }
+
+        let mut cleared = Vec::new();
+        for index in 0..self.blocks.len() {
+            if let Some(load) = self.blocks[index].load
+                && let Some(loaded) = self.blocks.get(load as usize)
+                && loaded.root == self.blocks[index].root
+            {
+                self.blocks[index].load = None;
+                cleared.push(index);
+            }
+        }
+        cleared
+    }
+
+    /// Moves blocks to a new root and returns its index.
+    ///
+    /// The new root gets the position and rotation of the first block. Loads are
+    /// handled as in [`Building::move_blocks`].
+    ///
+    /// # Panics
+    /// Panics if any of the blocks doesn't exist.
+    pub fn split_root(&mut self, blocks: &[usize]) -> (u16, Vec<usize>) {
+        let root = match blocks.first() {
+            Some(&first) => Root { position: self.blocks[first].position, rotation: self.blocks[first].rotation },
+            None => Root::default(),
+        };
+        let root = self.add_root(root);
+        (root, self.move_blocks(blocks, root))
+    }
+
+    /// Removes a root that has no blocks and shifts the roots after it.
+    ///
+    /// Returns `None` and leaves the building unchanged if blocks are still
+    /// attached to the root or it doesn't exist.
+    pub fn remove_root(&mut self, root: u16) -> Option<Root> {
+        if root as usize >= self.roots.len() || self.blocks.iter().any(|b| b.root == root) {
+            return None;
+        }
+
+        let removed = self.roots.remove(root as usize);
+        for block in self.blocks.iter_mut() {
+            if block.root > root {
+                block.root -= 1;
+            }
+        }
+        Some(removed)
+    }
+
+    /// Removes all roots without blocks and compacts the root indices.
+    ///
+    /// Returns the number of removed roots.
+    pub fn remove_empty_roots(&mut self) -> usize {
+        let mut used = vec![false; self.roots.len()];
+        for block in self.blocks.iter() {
+            if let Some(used) = used.get_mut(block.root as usize) {
+                *used = true;
+            }
+        }
+
+        // New index of every kept root.
+        let mut new_index = Vec::with_capacity(used.len());
+        let mut next = 0u16;
+        for &used in used.iter() {
+            new_index.push(next);
+            if used {
+                next += 1;
+            }
+        }
+
+        let mut used_iter = used.iter();
+        self.roots.retain(|_| *used_iter.next().unwrap());
+        for block in self.blocks.iter_mut() {
+            if let Some(&index) = new_index.get(block.root as usize) {
+                block.root = index;
+            }
+        }
+
+        used.len() - self.roots.len()
+    }
+}
+
+#[test]
+fn test_roots() {
+    use crate::structs::*;
+
+    let mut building = Building::default();
+    building.add_root(Root::default());
+    building.add_root(Root::default());
+    building.blocks.push(Block { load: Some(1), ..Default::default() });
+    building.blocks.push(Block { root: 1, position: [1.0, 2.0, 3.0], ..Default::default() });
+    building.blocks.push(Block { root: 1, load: Some(0), ..Default::default() });
+    assert!(building.validate().is_empty());
+
+    assert_eq!(building.remove_root(0), None);
+
+    let (root, cleared) = building.split_root(&[1]);
+    assert_eq!(root, 2);
+    assert_eq!(building.roots[2].position, [1.0, 2.0, 3.0]);
+    assert!(cleared.is_empty());
+    assert_eq!(building.root_blocks(2).collect::<Vec<_>>(), vec![1]);
+
+    assert_eq!(building.move_blocks(&[2], 0), vec![2]);
+    assert_eq!(building.blocks[2].load, None);
+    assert!(building.validate().is_empty());
+
+    assert_eq!(building.remove_empty_roots(), 1);
+    assert_eq!(building.roots.len(), 2);
+    assert_eq!(building.blocks[1].root, 1);
+    assert!(building.validate().is_empty());
+
+    assert!(building.remove_root(1).is_none());
+    building.move_blocks(&[1], 0);
+    assert!(building.remove_root(1).is_some());
+}
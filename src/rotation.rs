@@ -0,0 +1,277 @@
+//! Rotation math for the Euler angles stored in blocks and roots.
+//!
+//! Rotations are stored as Euler angles in degrees. They are assumed to follow
+//! Unity's convention: rotate around Z first, then X, then Y, all around the
+//! world axes. As a matrix acting on column vectors that is `Ry * Rx * Rz`, as a
+//! quaternion `qy * qx * qz`.
+//!
+//! Many Euler triples describe the same rotation (`[0, 0, 360]` and `[0, 0, 0]`,
+//! or `[180, 0, 0]` and `[0, 180, 180]`). [`canonicalize`] picks one of them,
+//! [`angle_between`] and [`eq_quantized`] compare rotations rather than triples.
+//!
+//! ```rust
+//! use sw_structure_io::rotation::{canonicalize, compose, eq_quantized};
+//!
+//! assert!(eq_quantized([180.0, 0.0, 0.0], [0.0, 180.0, 180.0]));
+//! assert!(eq_quantized(compose([0.0, 90.0, 0.0], [0.0, 90.0, 0.0]), [0.0, 180.0, 0.0]));
+//! assert_eq!(canonicalize([0.0, -90.0, 0.0]), [0.0, 270.0, 0.0]);
+//! ```
+
+use crate::io::utils::{pack_rotation, unpack_rotation};
+
+/// A 3x3 rotation matrix, row-major, acting on column vectors.
+pub type Matrix = [[f32; 3]; 3];
+
+/// A unit quaternion as `[x, y, z, w]`, the same component order as Unity.
+pub type Quaternion = [f32; 4];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub(crate) fn index(self) -> usize {
+        match self {
+            Axis::X => 0,
+            Axis::Y => 1,
+            Axis::Z => 2,
+        }
+    }
+}
+
+/// Size of one step of the `u16` rotation quantization used by v6, in degrees.
+pub const QUANTUM: f32 = 360.0 / u16::MAX as f32;
+
+/// Below this `cos(x)`, the decomposition treats the rotation as gimbal locked.
+const GIMBAL_EPSILON: f32 = 1e-6;
+
+/// Sine and cosine of an angle in degrees, exact for multiples of 90°.
+fn sin_cos(degrees: f32) -> (f32, f32) {
+    let degrees = degrees.rem_euclid(360.0);
+    match degrees {
+        0.0 => (0.0, 1.0),
+        90.0 => (1.0, 0.0),
+        180.0 => (0.0, -1.0),
+        270.0 => (-1.0, 0.0),
+        _ => degrees.to_radians().sin_cos(),
+    }
+}
+
+/// Converts Euler angles in degrees to a rotation matrix.
+///
+/// The angles are applied in Unity's order, Z first, then X, then Y, so the
+/// result is `Ry * Rx * Rz`.
+pub fn euler_to_matrix(euler: [f32; 3]) -> Matrix {
+    let (sx, cx) = sin_cos(euler[0]);
+    let (sy, cy) = sin_cos(euler[1]);
+    let (sz, cz) = sin_cos(euler[2]);
+
+    [
+        [cy * cz + sy * sx * sz, -cy * sz + sy * sx * cz, sy * cx],
+        [cx * sz, cx * cz, -sx],
+        [-sy * cz + cy * sx * sz, sy * sz + cy * sx * cz, cy * cx],
+    ]
+}
+
+/// Decomposes a rotation matrix into Euler angles, each in `(-180, 180]`.
+///
+/// At gimbal lock (X at ±90°) the Z angle is set to 0.
+pub fn matrix_to_euler(m: Matrix) -> [f32; 3] {
+    let sx = (-m[1][2]).clamp(-1.0, 1.0);
+    let x = sx.asin();
+
+    let (y, z) = if x.cos() > GIMBAL_EPSILON {
+        (m[0][2].atan2(m[2][2]), m[1][0].atan2(m[1][1]))
+    } else {
+        ((-m[2][0]).atan2(m[0][0]), 0.0)
+    };
+
+    [x.to_degrees(), y.to_degrees(), z.to_degrees()]
+}
+
+/// Converts Euler angles in degrees to a unit quaternion.
+///
+/// The angles are applied in Unity's order, Z first, then X, then Y, so the
+/// result is `qy * qx * qz`.
+pub fn euler_to_quaternion(euler: [f32; 3]) -> Quaternion {
+    let axis = |i: usize, angle: f32| {
+        let (s, c) = (angle.to_radians() * 0.5).sin_cos();
+        let mut q = [0.0, 0.0, 0.0, c];
+        q[i] = s;
+        q
+    };
+
+    quaternion_mul(quaternion_mul(axis(1, euler[1]), axis(0, euler[0])), axis(2, euler[2]))
+}
+
+/// Converts a quaternion to Euler angles in degrees, in Unity's ZXY order and
+/// each in `(-180, 180]`, see [`matrix_to_euler`]. The quaternion doesn't need
+/// to be normalized.
+pub fn quaternion_to_euler(q: Quaternion) -> [f32; 3] {
+    matrix_to_euler(quaternion_to_matrix(q))
+}
+
+/// Converts a quaternion to a matrix. The quaternion doesn't need to be normalized.
+pub fn quaternion_to_matrix(q: Quaternion) -> Matrix {
+    let [x, y, z, w] = quaternion_normalize(q);
+
+    [
+        [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - z * w), 2.0 * (x * z + y * w)],
+        [2.0 * (x * y + z * w), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - x * w)],
+        [2.0 * (x * z - y * w), 2.0 * (y * z + x * w), 1.0 - 2.0 * (x * x + y * y)],
+    ]
+}
+
+/// Converts a rotation matrix to a unit quaternion.
+pub fn matrix_to_quaternion(m: Matrix) -> Quaternion {
+    let trace = m[0][0] + m[1][1] + m[2][2];
+
+    // Divide by the largest component to stay numerically stable.
+    let q = if trace > 0.0 {
+        let s = (trace + 1.0).sqrt() * 2.0;
+        [(m[2][1] - m[1][2]) / s, (m[0][2] - m[2][0]) / s, (m[1][0] - m[0][1]) / s, 0.25 * s]
+    } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+        let s = (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.0;
+        [0.25 * s, (m[0][1] + m[1][0]) / s, (m[0][2] + m[2][0]) / s, (m[2][1] - m[1][2]) / s]
+    } else if m[1][1] > m[2][2] {
+        let s = (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.0;
+        [(m[0][1] + m[1][0]) / s, 0.25 * s, (m[1][2] + m[2][1]) / s, (m[0][2] - m[2][0]) / s]
+    } else {
+        let s = (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.0;
+        [(m[0][2] + m[2][0]) / s, (m[1][2] + m[2][1]) / s, 0.25 * s, (m[1][0] - m[0][1]) / s]
+    };
+
+    quaternion_normalize(q)
+}
+
+/// Hamilton product `a * b`, the rotation `b` followed by `a`.
+pub fn quaternion_mul(a: Quaternion, b: Quaternion) -> Quaternion {
+    let [ax, ay, az, aw] = a;
+    let [bx, by, bz, bw] = b;
+
+    [
+        aw * bx + ax * bw + ay * bz - az * by,
+        aw * by - ax * bz + ay * bw + az * bx,
+        aw * bz + ax * by - ay * bx + az * bw,
+        aw * bw - ax * bx - ay * by - az * bz,
+    ]
+}
+
+fn quaternion_normalize(q: Quaternion) -> Quaternion {
+    let length = q.iter().map(|c| c * c).sum::<f32>().sqrt();
+    if length == 0.0 {
+        return [0.0, 0.0, 0.0, 1.0];
+    }
+    q.map(|c| c / length)
+}
+
+/// Matrix product `a * b`, the rotation `b` followed by `a`.
+pub fn mul(a: Matrix, b: Matrix) -> Matrix {
+    let mut out = [[0.0f32; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    out
+}
+
+/// Rotates a vector by a matrix.
+pub fn transform(m: Matrix, v: [f32; 3]) -> [f32; 3] {
+    [
+        m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+        m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+        m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+    ]
+}
+
+/// Returns `euler` rotated by `by`, i.e. `by` applied after `euler`.
+pub fn compose(by: [f32; 3], euler: [f32; 3]) -> [f32; 3] {
+    matrix_to_euler(mul(euler_to_matrix(by), euler_to_matrix(euler)))
+}
+
+/// Returns the rotation that undoes `euler`.
+pub fn inverse(euler: [f32; 3]) -> [f32; 3] {
+    let m = euler_to_matrix(euler);
+    matrix_to_euler(std::array::from_fn(|i| std::array::from_fn(|j| m[j][i])))
+}
+
+/// Mirrors a rotation across the plane orthogonal to `axis`.
+///
+/// A reflection can't be expressed as a rotation, so the result is the rotation
+/// conjugated by the reflection: orientation vectors are reflected and the
+/// handedness is restored by flipping the one along `axis`.
+pub fn mirror(euler: [f32; 3], axis: Axis) -> [f32; 3] {
+    let axis = axis.index();
+    let mut m = euler_to_matrix(euler);
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            if (i == axis) != (j == axis) {
+                *value = -*value;
+            }
+        }
+    }
+    matrix_to_euler(m)
+}
+
+/// Returns the canonical Euler angles of a rotation, each in `[0, 360)`.
+///
+/// Equivalent triples give the same result up to floating point error.
+pub fn canonicalize(euler: [f32; 3]) -> [f32; 3] {
+    matrix_to_euler(euler_to_matrix(euler)).map(|angle| {
+        let angle = angle.rem_euclid(360.0);
+        // Tiny negative angles round up to exactly 360.
+        if angle >= 360.0 { 0.0 } else { angle + 0.0 }
+    })
+}
+
+/// Returns the angle in degrees of the rotation from `a` to `b`, in `[0, 180]`.
+pub fn angle_between(a: [f32; 3], b: [f32; 3]) -> f32 {
+    let [x, y, z, w] = euler_to_quaternion(a);
+    let r = quaternion_mul([-x, -y, -z, w], euler_to_quaternion(b));
+    let sin = (r[0] * r[0] + r[1] * r[1] + r[2] * r[2]).sqrt();
+    (2.0 * sin.atan2(r[3].abs())).to_degrees()
+}
+
+/// Returns `euler` as it reads back after being stored in `u16` steps.
+pub fn quantize(euler: [f32; 3]) -> [f32; 3] {
+    unpack_rotation(pack_rotation(euler))
+}
+
+/// Returns `true` if `a` and `b` are the same rotation, allowing for the error
+/// of storing both in `u16` steps.
+///
+/// Each angle is off by at most half a [`QUANTUM`] after quantization, so the two
+/// rotations are compared with a tolerance of three quanta.
+pub fn eq_quantized(a: [f32; 3], b: [f32; 3]) -> bool {
+    angle_between(a, b) <= 3.0 * QUANTUM
+}
+
+#[test]
+fn test_euler_matrix_roundtrip() {
+    let close = |a: Matrix, b: Matrix| a.iter().flatten().zip(b.iter().flatten()).all(|(a, b)| (a - b).abs() < 1e-5);
+
+    for euler in [[0.0, 0.0, 0.0], [10.0, 20.0, 30.0], [-45.0, 170.0, 95.0], [90.0, 30.0, 0.0], [-90.0, 10.0, 0.0]] {
+        let m = euler_to_matrix(euler);
+        assert!(close(euler_to_matrix(matrix_to_euler(m)), m), "{euler:?}");
+        assert!(close(quaternion_to_matrix(euler_to_quaternion(euler)), m), "{euler:?}");
+        assert!(close(quaternion_to_matrix(matrix_to_quaternion(m)), m), "{euler:?}");
+        assert!(angle_between(quaternion_to_euler(euler_to_quaternion(euler)), euler) < 0.01);
+        assert!(eq_quantized(compose(inverse(euler), euler), [0.0; 3]));
+    }
+
+    // Z is applied first: rotating X by 90° around Z, then around Y by 90°.
+    let v = transform(euler_to_matrix([0.0, 90.0, 90.0]), [1.0, 0.0, 0.0]);
+    assert!((v[0]).abs() < 1e-6 && (v[1] - 1.0).abs() < 1e-6 && v[2].abs() < 1e-6);
+
+    let mirrored = euler_to_matrix(mirror([0.0, 90.0, 0.0], Axis::X));
+    assert!(close(mirrored, euler_to_matrix([0.0, -90.0, 0.0])));
+
+    assert_eq!(canonicalize([0.0, 0.0, 360.0]), [0.0, 0.0, 0.0]);
+    assert!((angle_between([0.0, 0.0, 10.0], [0.0, 0.0, -10.0]) - 20.0).abs() < 1e-3);
+    assert!(eq_quantized(quantize([12.3, 45.6, 78.9]), [12.3, 45.6, 78.9]));
+    assert!(!eq_quantized([0.0, 0.0, 0.0], [0.0, 0.0, 0.1]));
+}
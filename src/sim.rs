@@ -0,0 +1,290 @@
+//! Tick-based simulation of logic signals flowing along block connections.
+//!
+//! Every block has a state, initialized from
+//! [`Block::enable_state_current`]. On each tick every block computes its new
+//! state from the states its incoming connections had on the previous tick, so a
+//! signal travels one connection per tick. [`Block::connections`] are outgoing:
+//! a block's state is an input of every block it is connected to.
+//!
+//! How a block computes its state is defined by a [`Behaviour`]. Math blocks
+//! evaluate their function (see [`crate::math`]). The ids of gates and sensors
+//! aren't in the [catalog](crate::catalog) yet, so their behaviours are registered
+//! per block type with [`Simulator::set_kind_behaviour`], or per block with
+//! [`Simulator::set_block_behaviour`] to stub sensors. Blocks without a behaviour
+//! keep their state, which can be driven from the outside with
+//! [`Simulator::set_state`].
+//!
+//! ```rust
+//! use sw_structure_io::catalog::BlockKind;
+//! use sw_structure_io::sim::{Context, Simulator};
+//! use sw_structure_io::structs::*;
+//!
+//! const NOT_GATE: BlockKind = BlockKind(42);
+//!
+//! let mut building = Building::default();
+//! building.roots.push(Root::default());
+//! building.blocks.push(Block { connections: vec![1], ..Default::default() });
+//! building.blocks.push(Block { id: NOT_GATE, ..Default::default() });
+//!
+//! let mut sim = Simulator::new(&building);
+//! sim.set_kind_behaviour(NOT_GATE, |ctx: &Context| {
+//!     if ctx.inputs.iter().any(|&i| i > 0.0) { 0.0 } else { 1.0 }
+//! });
+//!
+//! sim.tick();
+//! assert_eq!(sim.state(1), 1.0);
+//!
+//! sim.set_state(0, 1.0);
+//! sim.tick();
+//! assert_eq!(sim.state(1), 0.0);
+//! ```
+
+use std::collections::HashMap;
+
+use crate::catalog::{BlockKind, TypeSettingsKind};
+use crate::math::{self, Expr};
+use crate::structs::{Block, Building, TypeSettings};
+
+/// What a [`Behaviour`] sees of its block on a tick.
+#[derive(Clone, Copy, Debug)]
+pub struct Context<'a> {
+    /// Index of the block in the building.
+    pub index: usize,
+
+    pub block: &'a Block,
+
+    /// States of the blocks connected to this one on the previous tick, in
+    /// ascending block index order.
+    pub inputs: &'a [f32],
+
+    /// Block indices of `inputs`.
+    pub sources: &'a [usize],
+
+    /// State of the block on the previous tick.
+    pub state: f32,
+
+    /// Number of ticks simulated before this one.
+    pub tick: u64,
+}
+
+/// Computes the new state of a block.
+///
+/// Implemented for closures taking a [`Context`].
+pub trait Behaviour {
+    fn tick(&mut self, ctx: &Context) -> f32;
+}
+
+impl<F: FnMut(&Context) -> f32> Behaviour for F {
+    fn tick(&mut self, ctx: &Context) -> f32 {
+        self(ctx)
+    }
+}
+
+/// Evaluates the function of a math block.
+///
+/// Slots are filled from `incoming_connections_order` and `slots`. A function
+/// that doesn't parse outputs `0.0`, [`Building::lint_math`] reports it.
+struct MathBehaviour {
+    expr: Option<Expr>,
+    /// `(source block, slot)` pairs.
+    assignments: Vec<(usize, u8)>,
+}
+
+impl Behaviour for MathBehaviour {
+    fn tick(&mut self, ctx: &Context) -> f32 {
+        let Some(expr) = &self.expr else {
+            return 0.0;
+        };
+
+        let mut inputs = Vec::new();
+        for &(source, slot) in &self.assignments {
+            let Some(i) = ctx.sources.iter().position(|&s| s == source) else {
+                continue;
+            };
+            if inputs.len() <= slot as usize {
+                inputs.resize(slot as usize + 1, 0.0);
+            }
+            inputs[slot as usize] = ctx.inputs[i];
+        }
+
+        expr.eval(&inputs)
+    }
+}
+
+/// Simulates the signals of a building, see the [module documentation](self).
+pub struct Simulator<'a> {
+    building: &'a Building,
+    states: Vec<f32>,
+    /// Source blocks of every block, ascending.
+    incoming: Vec<Vec<usize>>,
+    kind_behaviours: HashMap<BlockKind, Box<dyn Behaviour + 'a>>,
+    block_behaviours: HashMap<usize, Box<dyn Behaviour + 'a>>,
+    tick: u64,
+}
+
+impl<'a> Simulator<'a> {
+    /// Creates a simulator with the current states of the building's blocks.
+    ///
+    /// Connections to blocks that don't exist are ignored.
+    pub fn new(building: &'a Building) -> Self {
+        let mut incoming = vec![Vec::new(); building.blocks.len()];
+        for (index, block) in building.blocks.iter().enumerate() {
+            for &c in &block.connections {
+                if let Some(sources) = incoming.get_mut(c as usize) {
+                    sources.push(index);
+                }
+            }
+        }
+        for sources in incoming.iter_mut() {
+            sources.dedup();
+        }
+
+        let mut block_behaviours: HashMap<usize, Box<dyn Behaviour + 'a>> = HashMap::new();
+        for (index, block) in building.blocks.iter().enumerate() {
+            if block.id.type_settings_kind() != TypeSettingsKind::MathBlock {
+                continue;
+            }
+            if let Some(metadata) = &block.metadata
+                && let TypeSettings::MathBlock { function, incoming_connections_order, slots } = &metadata.type_settings
+            {
+                block_behaviours.insert(index, Box::new(MathBehaviour {
+                    expr: math::parse(function).ok(),
+                    assignments: incoming_connections_order
+                        .iter()
+                        .zip(slots.iter())
+                        .map(|(&source, &slot)| (source as usize, slot))
+                        .collect(),
+                }));
+            }
+        }
+
+        Self {
+            building,
+            states: building.blocks.iter().map(|b| b.enable_state_current).collect(),
+            incoming,
+            kind_behaviours: HashMap::new(),
+            block_behaviours,
+            tick: 0,
+        }
+    }
+
+    /// Sets the behaviour of every block of a type, replacing the previous one.
+    ///
+    /// Block behaviours set with [`Simulator::set_block_behaviour`] take precedence.
+    pub fn set_kind_behaviour(&mut self, kind: BlockKind, behaviour: impl Behaviour + 'a) {
+        self.kind_behaviours.insert(kind, Box::new(behaviour));
+    }
+
+    /// Sets the behaviour of a single block, replacing the previous one (including
+    /// the built-in behaviour of math blocks).
+    pub fn set_block_behaviour(&mut self, index: usize, behaviour: impl Behaviour + 'a) {
+        self.block_behaviours.insert(index, Box::new(behaviour));
+    }
+
+    /// Returns the state of a block.
+    ///
+    /// # Panics
+    /// Panics if the block doesn't exist.
+    pub fn state(&self, index: usize) -> f32 {
+        self.states[index]
+    }
+
+    /// Returns the states of all blocks.
+    pub fn states(&self) -> &[f32] {
+        &self.states
+    }
+
+    /// Overrides the state of a block, e.g. to press a button.
+    ///
+    /// # Panics
+    /// Panics if the block doesn't exist.
+    pub fn set_state(&mut self, index: usize, state: f32) {
+        self.states[index] = state;
+    }
+
+    /// Returns the number of ticks simulated so far.
+    pub fn ticks(&self) -> u64 {
+        self.tick
+    }
+
+    /// Advances the simulation by one tick.
+    pub fn tick(&mut self) {
+        let mut states = self.states.clone();
+        let mut inputs = Vec::new();
+
+        for (index, block) in self.building.blocks.iter().enumerate() {
+            let behaviour = match self.block_behaviours.get_mut(&index) {
+                Some(behaviour) => behaviour,
+                None => match self.kind_behaviours.get_mut(&block.id) {
+                    Some(behaviour) => behaviour,
+                    None => continue,
+                },
+            };
+
+            let sources = &self.incoming[index];
+            inputs.clear();
+            inputs.extend(sources.iter().map(|&s| self.states[s]));
+
+            states[index] = behaviour.tick(&Context {
+                index,
+                block,
+                inputs: &inputs,
+                sources,
+                state: self.states[index],
+                tick: self.tick,
+            });
+        }
+
+        self.states = states;
+        self.tick += 1;
+    }
+
+    /// Advances the simulation by `ticks` ticks.
+    pub fn run(&mut self, ticks: u64) {
+        for _ in 0..ticks {
+            self.tick();
+        }
+    }
+}
+
+#[test]
+fn test_simulator() {
+    use crate::structs::*;
+
+    const AND_GATE: BlockKind = BlockKind(42);
+
+    let mut building = Building::default();
+    building.roots.push(Root::default());
+    building.blocks.push(Block { connections: vec![2, 3], enable_state_current: 1.0, ..Default::default() });
+    building.blocks.push(Block { connections: vec![2, 3], ..Default::default() });
+    building.blocks.push(Block { id: AND_GATE, connections: vec![3], ..Default::default() });
+    building.blocks.push(Block {
+        id: BlockKind::MATH_BLOCK,
+        metadata: Some(Metadata {
+            type_settings: TypeSettings::MathBlock {
+                function: "a + b * 2 + c * 10".to_string(),
+                incoming_connections_order: vec![1, 0, 2],
+                slots: vec![0, 1, 2],
+            },
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+
+    let mut sim = Simulator::new(&building);
+    sim.set_kind_behaviour(AND_GATE, |ctx: &Context| {
+        if ctx.inputs.iter().all(|&i| i > 0.0) { 1.0 } else { 0.0 }
+    });
+
+    sim.tick();
+    assert_eq!(sim.states(), &[1.0, 0.0, 0.0, 2.0]);
+
+    sim.set_state(1, 3.0);
+    sim.run(2);
+    assert_eq!(sim.states(), &[1.0, 3.0, 1.0, 15.0]);
+    assert_eq!(sim.ticks(), 3);
+
+    sim.set_block_behaviour(1, |ctx: &Context| ctx.tick as f32);
+    sim.tick();
+    assert_eq!(sim.state(1), 3.0);
+}
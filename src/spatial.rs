@@ -0,0 +1,449 @@
+//! Bounding boxes and spatial queries over block positions.
+//!
+//! The queries on [`Building`] scan every block, which is fine for one-off
+//! questions. For many queries on large buildings, build a [`SpatialIndex`] once
+//! and query that instead.
+//!
+//! ```rust
+//! use sw_structure_io::spatial::SpatialIndex;
+//! use sw_structure_io::structs::*;
+//!
+//! let mut building = Building::default();
+//! building.roots.push(Root::default());
+//! for x in 0..10 {
+//!     building.blocks.push(Block { position: [x as f32, 0.0, 0.0], ..Default::default() });
+//! }
+//!
+//! let index = SpatialIndex::new(&building, 2.0);
+//! assert_eq!(index.nearest([3.2, 1.0, 0.0]), Some(3));
+//! assert_eq!(index.in_sphere([0.0; 3], 1.5), vec![0, 1]);
+//! ```
+
+use std::collections::HashMap;
+
+use crate::structs::Building;
+
+/// An axis-aligned bounding box.
+///
+/// A new box is empty (`min` above `max`) until a point is added with
+/// [`Bounds::encapsulate`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bounds {
+    /// Creates an empty box.
+    pub const fn new() -> Self {
+        Bounds {
+            min: [f32::INFINITY; 3],
+            max: [f32::NEG_INFINITY; 3],
+        }
+    }
+
+    pub const fn from_center_and_size(center: [f32; 3], size: [f32; 3]) -> Self {
+        let mut min = [0.0f32; 3];
+        let mut max = [0.0f32; 3];
+
+        let mut i = 0;
+        while i < 3 {
+            min[i] = center[i] - size[i] * 0.5;
+            max[i] = center[i] + size[i] * 0.5;
+            i += 1;
+        }
+
+        Self { min, max }
+    }
+
+    pub const fn get_center_and_size(&self) -> ([f32; 3], [f32; 3]) {
+        let mut center = [0.0f32; 3];
+        let mut size = [0.0f32; 3];
+
+        let mut i = 0;
+        while i < 3 {
+            center[i] = (self.min[i] + self.max[i]) * 0.5;
+            size[i] = self.max[i] - self.min[i];
+            i += 1;
+        }
+
+        (center, size)
+    }
+
+    /// Returns `true` if no point was added yet.
+    pub fn is_empty(&self) -> bool {
+        (0..3).any(|i| self.min[i] > self.max[i])
+    }
+
+    /// Grows the box to contain `point`.
+    pub fn encapsulate(&mut self, point: &[f32; 3]) {
+        for ((min, max), &p) in self.min.iter_mut().zip(self.max.iter_mut()).zip(point) {
+            *min = min.min(p);
+            *max = max.max(p);
+        }
+    }
+
+    /// Returns `true` if `point` is inside the box or on its surface.
+    pub fn contains(&self, point: [f32; 3]) -> bool {
+        (0..3).all(|i| self.min[i] <= point[i] && point[i] <= self.max[i])
+    }
+
+    /// Returns `true` if the boxes overlap or touch.
+    pub fn intersects(&self, other: &Bounds) -> bool {
+        (0..3).all(|i| self.min[i] <= other.max[i] && other.min[i] <= self.max[i])
+    }
+}
+
+fn distance_squared(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum()
+}
+
+impl Building {
+    /// Returns the bounding box of all block positions.
+    pub fn bounds(&self) -> Bounds {
+        let mut bounds = Bounds::new();
+        for block in self.blocks.iter() {
+            bounds.encapsulate(&block.position);
+        }
+        bounds
+    }
+
+    /// Returns the bounding box of the positions of the blocks attached to `root`.
+    pub fn root_bounds(&self, root: u16) -> Bounds {
+        let mut bounds = Bounds::new();
+        for block in self.blocks.iter().filter(|b| b.root == root) {
+            bounds.encapsulate(&block.position);
+        }
+        bounds
+    }
+
+    /// Returns the blocks whose position is inside `bounds`, ascending.
+    pub fn blocks_in_box(&self, bounds: &Bounds) -> Vec<usize> {
+        (0..self.blocks.len()).filter(|&i| bounds.contains(self.blocks[i].position)).collect()
+    }
+
+    /// Returns the blocks within `radius` of `center`, ascending.
+    pub fn blocks_in_sphere(&self, center: [f32; 3], radius: f32) -> Vec<usize> {
+        (0..self.blocks.len())
+            .filter(|&i| distance_squared(self.blocks[i].position, center) <= radius * radius)
+            .collect()
+    }
+
+    /// Returns the block closest to `point`, the lowest index on ties.
+    pub fn nearest_block(&self, point: [f32; 3]) -> Option<usize> {
+        (0..self.blocks.len()).min_by(|&a, &b| {
+            distance_squared(self.blocks[a].position, point)
+                .total_cmp(&distance_squared(self.blocks[b].position, point))
+        })
+    }
+}
+
+type Cell = [i32; 3];
+
+/// A uniform grid over block positions for fast spatial queries.
+///
+/// The index is a snapshot: it has to be rebuilt after blocks are moved, added
+/// or removed. Results are the same as those of the scanning queries on
+/// [`Building`].
+#[derive(Clone, Debug)]
+pub struct SpatialIndex {
+    cell_size: f32,
+    positions: Vec<[f32; 3]>,
+    cells: HashMap<Cell, Vec<usize>>,
+    /// Range of occupied cells, bounds the search of [`SpatialIndex::nearest`].
+    min_cell: Cell,
+    max_cell: Cell,
+}
+
+impl SpatialIndex {
+    /// Indexes the block positions of a building in cubic cells of `cell_size`.
+    ///
+    /// A cell size around the typical distance between neighbouring blocks works
+    /// well.
+    ///
+    /// # Panics
+    /// Panics if `cell_size` is not positive and finite.
+    pub fn new(building: &Building, cell_size: f32) -> Self {
+        assert!(cell_size > 0.0 && cell_size.is_finite(), "invalid cell size {cell_size}");
+
+        let mut index = Self {
+            cell_size,
+            positions: building.blocks.iter().map(|b| b.position).collect(),
+            cells: HashMap::new(),
+            min_cell: [i32::MAX; 3],
+            max_cell: [i32::MIN; 3],
+        };
+
+        for (i, &position) in index.positions.iter().enumerate() {
+            let cell = index.cell(position);
+            index.min_cell = std::array::from_fn(|axis| index.min_cell[axis].min(cell[axis]));
+            index.max_cell = std::array::from_fn(|axis| index.max_cell[axis].max(cell[axis]));
+            index.cells.entry(cell).or_default().push(i);
+        }
+
+        index
+    }
+
+    fn cell(&self, position: [f32; 3]) -> Cell {
+        position.map(|p| (p / self.cell_size).floor() as i32)
+    }
+
+    /// Calls `f` with the blocks of every cell overlapping `bounds`.
+    fn visit(&self, bounds: &Bounds, mut f: impl FnMut(usize)) {
+        let (min, max) = (self.cell(bounds.min), self.cell(bounds.max));
+        let clamp = |axis: usize| {
+            (min[axis].max(self.min_cell[axis]), max[axis].min(self.max_cell[axis]))
+        };
+        let ((x0, x1), (y0, y1), (z0, z1)) = (clamp(0), clamp(1), clamp(2));
+
+        // Sparse buildings can span far more cells than are occupied, then it is
+        // cheaper to go through the occupied ones.
+        let volume = [(x0, x1), (y0, y1), (z0, z1)]
+            .iter()
+            .map(|&(lo, hi)| (hi as i64 - lo as i64 + 1).max(0) as u128)
+            .product::<u128>();
+        if volume > self.cells.len() as u128 {
+            for (cell, blocks) in self.cells.iter() {
+                if (x0..=x1).contains(&cell[0]) && (y0..=y1).contains(&cell[1]) && (z0..=z1).contains(&cell[2]) {
+                    blocks.iter().for_each(|&i| f(i));
+                }
+            }
+            return;
+        }
+
+        for x in x0..=x1 {
+            for y in y0..=y1 {
+                for z in z0..=z1 {
+                    if let Some(blocks) = self.cells.get(&[x, y, z]) {
+                        blocks.iter().for_each(|&i| f(i));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Calls `f` with the blocks of the cells on the surface of the cube of cells
+    /// reaching `ring` cells out from `center`.
+    fn visit_ring(&self, center: Cell, ring: i64, mut f: impl FnMut(usize)) {
+        // Offsets from `center` that are both on the cube and occupied.
+        let range = |axis: usize| {
+            let c = center[axis] as i64;
+            ((self.min_cell[axis] as i64 - c).max(-ring), (self.max_cell[axis] as i64 - c).min(ring))
+        };
+        let ((x0, x1), (y0, y1), (z0, z1)) = (range(0), range(1), range(2));
+
+        let mut visit = |offset: [i64; 3]| {
+            let cell = std::array::from_fn(|axis| (center[axis] as i64 + offset[axis]) as i32);
+            if let Some(blocks) = self.cells.get(&cell) {
+                blocks.iter().for_each(|&i| f(i));
+            }
+        };
+
+        for x in x0..=x1 {
+            for y in y0..=y1 {
+                if x.abs() == ring || y.abs() == ring {
+                    for z in z0..=z1 {
+                        visit([x, y, z]);
+                    }
+                } else {
+                    // Inside the x and y faces only the two z faces are on the surface.
+                    for z in [-ring, ring].into_iter().filter(|z| (z0..=z1).contains(z)) {
+                        visit([x, y, z]);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Number of cells [`SpatialIndex::visit_ring`] looks up for `ring`.
+    fn ring_cells(&self, center: Cell, ring: i64) -> u128 {
+        // Cells of the cube reaching `ring` out that are in the occupied range.
+        let cube = |ring: i64| {
+            (0..3)
+                .map(|axis| {
+                    let c = center[axis] as i64;
+                    let lo = (self.min_cell[axis] as i64).max(c - ring);
+                    let hi = (self.max_cell[axis] as i64).min(c + ring);
+                    (hi - lo + 1).max(0) as u128
+                })
+                .product::<u128>()
+        };
+        if ring == 0 { cube(0) } else { cube(ring) - cube(ring - 1) }
+    }
+
+    /// Returns the blocks whose position is inside `bounds`, ascending.
+    pub fn in_box(&self, bounds: &Bounds) -> Vec<usize> {
+        let mut found = Vec::new();
+        if bounds.is_empty() {
+            return found;
+        }
+        self.visit(bounds, |i| {
+            if bounds.contains(self.positions[i]) {
+                found.push(i);
+            }
+        });
+        found.sort_unstable();
+        found
+    }
+
+    /// Returns the blocks within `radius` of `center`, ascending.
+    pub fn in_sphere(&self, center: [f32; 3], radius: f32) -> Vec<usize> {
+        let mut found = Vec::new();
+        if radius < 0.0 {
+            return found;
+        }
+        let bounds = Bounds::from_center_and_size(center, [radius * 2.0; 3]);
+        self.visit(&bounds, |i| {
+            if distance_squared(self.positions[i], center) <= radius * radius {
+                found.push(i);
+            }
+        });
+        found.sort_unstable();
+        found
+    }
+
+    /// Returns the block closest to `point`, the lowest index on ties.
+    pub fn nearest(&self, point: [f32; 3]) -> Option<usize> {
+        if self.positions.is_empty() {
+            return None;
+        }
+
+        let center = self.cell(point);
+        // Ring of cells at which every occupied cell has been visited.
+        let last_ring = (0..3)
+            .map(|axis| {
+                (center[axis] as i64 - self.min_cell[axis] as i64)
+                    .abs()
+                    .max((self.max_cell[axis] as i64 - center[axis] as i64).abs())
+            })
+            .max()
+            .unwrap_or(0);
+
+        // Rings closer than this don't reach any occupied cell.
+        let first_ring = (0..3)
+            .map(|axis| {
+                let c = center[axis] as i64;
+                (self.min_cell[axis] as i64 - c).max(c - self.max_cell[axis] as i64).max(0)
+            })
+            .max()
+            .unwrap_or(0);
+
+        let mut best: Option<(f32, usize)> = None;
+        let consider = |best: &mut Option<(f32, usize)>, i: usize| {
+            let d = distance_squared(self.positions[i], point);
+            if best.is_none_or(|(best_d, best_i)| d < best_d || (d == best_d && i < best_i)) {
+                *best = Some((d, i));
+            }
+        };
+        // Blocks on rings past `ring` are at least `ring * cell_size` away.
+        let done = |best: Option<(f32, usize)>, ring: i64| {
+            let reach = ring as f32 * self.cell_size;
+            best.is_some_and(|(d, _)| d < reach * reach)
+        };
+
+        for ring in first_ring..=last_ring {
+            // Far out in sparse buildings a ring has more cells than are occupied,
+            // then it is cheaper to go through the remaining occupied ones by distance.
+            if self.ring_cells(center, ring) > self.cells.len() as u128 {
+                let ring_of = |cell: &Cell| {
+                    (0..3).map(|axis| (cell[axis] as i64 - center[axis] as i64).abs()).max().unwrap_or(0)
+                };
+                let mut remaining: Vec<_> = self
+                    .cells
+                    .iter()
+                    .map(|(cell, blocks)| (ring_of(cell), blocks))
+                    .filter(|&(cell_ring, _)| cell_ring >= ring)
+                    .collect();
+                remaining.sort_unstable_by_key(|&(cell_ring, _)| cell_ring);
+
+                for (cell_ring, blocks) in remaining {
+                    if done(best, cell_ring - 1) {
+                        break;
+                    }
+                    blocks.iter().for_each(|&i| consider(&mut best, i));
+                }
+                break;
+            }
+
+            self.visit_ring(center, ring, |i| consider(&mut best, i));
+            if done(best, ring) {
+                break;
+            }
+        }
+
+        best.map(|(_, i)| i)
+    }
+}
+
+#[test]
+fn test_spatial_index() {
+    use crate::structs::*;
+    use rand::Rng;
+
+    let mut rng = rand::rng();
+    let mut building = Building::default();
+    building.roots.push(Root::default());
+    building.roots.push(Root::default());
+    for i in 0..2000 {
+        let position = std::array::from_fn(|_| rng.random_range(-50.0..50.0));
+        building.blocks.push(Block { position, root: (i % 2) as u16, ..Default::default() });
+    }
+
+    let bounds = building.bounds();
+    assert!(building.blocks.iter().all(|b| bounds.contains(b.position)));
+    assert!(building.root_bounds(1).intersects(&bounds));
+    assert!(building.root_bounds(2).is_empty());
+
+    let index = SpatialIndex::new(&building, 4.0);
+    for _ in 0..100 {
+        let point: [f32; 3] = std::array::from_fn(|_| rng.random_range(-70.0..70.0));
+        let radius = rng.random_range(0.0..20.0);
+        let query = Bounds::from_center_and_size(point, [radius; 3]);
+
+        assert_eq!(index.nearest(point), building.nearest_block(point));
+        assert_eq!(index.in_sphere(point, radius), building.blocks_in_sphere(point, radius));
+        assert_eq!(index.in_box(&query), building.blocks_in_box(&query));
+    }
+
+    // Every block is on exactly one ring.
+    let center = index.cell([3.0, -7.0, 20.0]);
+    let mut visited = vec![0; building.blocks.len()];
+    for ring in 0..=40 {
+        index.visit_ring(center, ring, |i| visited[i] += 1);
+    }
+    assert!(visited.iter().all(|&n| n == 1));
+
+    assert_eq!(SpatialIndex::new(&Building::default(), 1.0).nearest([0.0; 3]), None);
+
+    // Queries over sparse, far apart blocks don't go through every empty cell.
+    let mut building = Building::default();
+    building.roots.push(Root::default());
+    building.blocks.push(Block { position: [0.0; 3], ..Default::default() });
+    building.blocks.push(Block { position: [2000.0; 3], ..Default::default() });
+    let index = SpatialIndex::new(&building, 1.0);
+    assert_eq!(index.in_box(&building.bounds()), vec![0, 1]);
+    assert_eq!(index.in_sphere([1000.0; 3], 2000.0), vec![0, 1]);
+    assert_eq!(index.in_sphere([1000.0; 3], 10.0), Vec::<usize>::new());
+    assert_eq!(index.nearest([1100.0; 3]), Some(1));
+    assert_eq!(index.nearest([-5000.0, 0.0, 0.0]), Some(0));
+
+    // Two clusters far apart, queried from inside, between and beside them.
+    let mut building = Building::default();
+    building.roots.push(Root::default());
+    for i in 0..500 {
+        let offset = if i % 2 == 0 { 0.0 } else { 1.0e5 };
+        let position = std::array::from_fn(|_| offset + rng.random_range(-10.0..10.0));
+        building.blocks.push(Block { position, ..Default::default() });
+    }
+    let index = SpatialIndex::new(&building, 1.0);
+    for point in [[0.0; 3], [1.0e5; 3], [4.0e4; 3], [6.0e4; 3], [-3.0e4, 1.0e5, 0.0]] {
+        assert_eq!(index.nearest(point), building.nearest_block(point));
+    }
+}
+
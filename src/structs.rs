@@ -1,4 +1,7 @@
-#[derive(Debug, Default)]
+use crate::catalog::BlockKind;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// Represents an entire assembled structure.
 /// 
 /// A `Building` is composed of one or more roots (rigid bodies) and a flat list
@@ -13,9 +16,16 @@ pub struct Building {
     /// All blocks that belong to the building.  
     /// Blocks reference their parent root by index.
     pub blocks: Vec<Block>,
+
+    /// Bytes found after the building data when reading with
+    /// [`ReadOptions::trailing`](crate::io::ReadOptions::trailing), e.g. data appended
+    /// by a newer game build. They are written back unchanged after the building data.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub trailing: Vec<u8>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// A physically independent part of a building.
 /// 
 /// A `Root` is a rigid body that can contain multiple blocks.  
@@ -31,7 +41,8 @@ pub struct Root {
     pub rotation: [f32; 3],
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// A single element in a building.
 ///
 /// Every `Block` is **always part of a `Root`**, and its `root` field
@@ -47,8 +58,8 @@ pub struct Block {
     /// World-space rotation of the block.
     pub rotation: [f32; 3],
 
-    /// Numeric block‐type identifier.
-    pub id: u8,
+    /// Block type identifier, see [`BlockKind`].
+    pub id: BlockKind,
 
     /// Index of the root that this block belongs to.
     /// Every block must be attached to a root.
@@ -79,7 +90,8 @@ pub struct Block {
     pub color: Option<[u8; 4]>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// A color gradient consisting of color and alpha keys.
 /// 
 /// Each gradient is defined by color values over normalized time and alpha
@@ -91,7 +103,8 @@ pub struct Gradient {
     pub alpha_time_keys: Vec<f32>,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// All per-block editable settings.
 /// 
 /// `Metadata` contains a variety of UI-driven values used by different block
@@ -122,7 +135,9 @@ pub struct Metadata {
     pub type_settings: TypeSettings,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "data", rename_all = "snake_case"))]
 /// Additional metadata specific to certain block types.
 ///
 /// `TypeSettings` defines extra configuration for a block based on its type (`id`).
@@ -131,6 +146,7 @@ pub struct Metadata {
 /// configurations do not break anything.
 pub enum TypeSettings {
     /// No advanced settings.
+    #[default]
     None,
 
     /// Settings for math block, defining the computation and the placement of
@@ -142,6 +158,10 @@ pub enum TypeSettings {
     /// 
     /// Together, each `(incoming_connections_order[i], slots[i])` defines a
     /// connection-slot assignment.
+    ///
+    /// The serialized settings don't store the length of either vector. They
+    /// are written as given, but only read back if both hold one element per
+    /// input slot the function reads.
     MathBlock {
         /// The math expression to evaluate.
         function: String,
@@ -153,11 +173,49 @@ pub enum TypeSettings {
         /// Slots for the connected blocks. Each element pairs with the same-index
         /// element in `incoming_connections_order`.
         slots: Vec<u8>,
-    }
+    },
+
+    /// Settings of a block type whose layout isn't known yet (see
+    /// [`TypeSettingsKind::Unknown`](crate::catalog::TypeSettingsKind::Unknown)),
+    /// as the exact bytes to write.
+    ///
+    /// No reader produces this variant yet. The settings don't store their length
+    /// and the layout is unknown, so reading such a block fails with
+    /// [`ErrorKind::UnreadableTypeSettings`](crate::io::ErrorKind::UnreadableTypeSettings)
+    /// instead of passing the bytes through. Only writing is supported.
+    Raw(Vec<u8>),
 }
 
-impl Default for TypeSettings {
-    fn default() -> Self {
-        TypeSettings::None
-    }
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_representation() {
+    let mut building = Building::default();
+    building.roots.push(Root::default());
+    building.blocks.push(Block {
+        id: BlockKind::MATH_BLOCK,
+        color: Some([255, 0, 0, 255]),
+        metadata: Some(Metadata {
+            type_settings: TypeSettings::MathBlock {
+                function: "a".to_string(),
+                incoming_connections_order: vec![],
+                slots: vec![],
+            },
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+
+    let json = serde_json::to_value(&building).unwrap();
+    let block = &json["blocks"][0];
+    assert_eq!(block["id"], 129);
+    assert_eq!(block["color"], serde_json::json!([255, 0, 0, 255]));
+    assert_eq!(block["metadata"]["type_settings"]["type"], "math_block");
+    assert_eq!(block["metadata"]["type_settings"]["data"]["function"], "a");
+    assert!(json.get("trailing").is_none());
+
+    assert_eq!(serde_json::to_value(TypeSettings::None).unwrap(), serde_json::json!({"type": "none"}));
+    assert_eq!(serde_json::to_value(TypeSettings::Raw(vec![1])).unwrap(), serde_json::json!({"type": "raw", "data": [1]}));
+
+    let loaded: Building = serde_json::from_value(json).unwrap();
+    assert_eq!(loaded, building);
 }
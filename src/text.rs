@@ -0,0 +1,582 @@
+//! A line-oriented text format for buildings, meant for review and version control.
+//!
+//! The text format represents every field of a [`Building`], so converting a
+//! structure file to text and back writes the same bytes again. Floats are
+//! written in their shortest exact form, NaNs with their bits.
+//!
+//! ```text
+//! sw-structure text 1
+//! root 0.0 0.0 0.0 0.0 0.0 0.0
+//! block 0
+//!   id 129
+//!   root 0
+//!   position 1.0 0.5 0.0
+//!   rotation 0.0 90.0 0.0
+//!   name "Adder"
+//!   enable_state 0.0
+//!   enable_state_current 0.0
+//!   connections 1 2
+//!   load 3
+//!   color 255 0 0 255
+//!   metadata
+//!     toggles true false
+//!     values 0.25
+//!     field 1 2
+//!     dropdowns 7
+//!     color 1.0 0.0 0.0 1.0
+//!     vector 1.0 2.0 3.0
+//!     gradient
+//!       color_keys 0.0 0.0 0.0 1.0 1.0 1.0 1.0 1.0
+//!       color_time_keys 0.0 1.0
+//!       alpha_keys 1.0
+//!       alpha_time_keys 0.0
+//!     math_block "a + b"
+//!     incoming_connections_order 0 1
+//!     slots 0 1
+//! trailing abcd
+//! ```
+//!
+//! Every line is a keyword followed by its values. Indentation is only for
+//! readability: a line belongs to the last `block`, `metadata` or `gradient`
+//! above it. Lines that are absent mean `None` (`load`, `color`, `metadata`) or
+//! an empty list (`field`, `color`, `vector` and `gradient` lines inside
+//! `metadata` repeat once per element). Custom block settings are written as
+//! `raw` followed by hex bytes. Blank lines and lines starting with `#` are ignored.
+
+use std::fmt::{self, Write};
+
+use thiserror::Error;
+
+use crate::catalog::BlockKind;
+use crate::structs::{Block, Building, Gradient, Metadata, Root, TypeSettings};
+
+const HEADER: &str = "sw-structure text 1";
+
+/// Why a text building couldn't be read. Lines are 1-based.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum TextError {
+    #[error("missing \"{HEADER}\" header")]
+    MissingHeader,
+
+    #[error("line {line}: unknown keyword {keyword:?}")]
+    UnknownKeyword { line: usize, keyword: String },
+
+    #[error("line {line}: {keyword} is not allowed here")]
+    OutOfContext { line: usize, keyword: String },
+
+    #[error("line {line}: invalid value {value:?}")]
+    InvalidValue { line: usize, value: String },
+
+    #[error("line {line}: {keyword} expects {expected} values, found {found}")]
+    ValueCount { line: usize, keyword: String, expected: usize, found: usize },
+
+    #[error("line {line}: expected block {expected}, found block {found}")]
+    BlockIndex { line: usize, expected: usize, found: usize },
+
+    #[error("line {line}: unterminated string")]
+    UnterminatedString { line: usize },
+}
+
+/// Returns the text form of a building.
+pub fn to_text(building: &Building) -> String {
+    let mut out = String::new();
+    write_text(&mut out, building).expect("writing to a String can't fail");
+    out
+}
+
+/// Writes the text form of a building.
+pub fn write_text(w: &mut impl Write, building: &Building) -> fmt::Result {
+    writeln!(w, "{HEADER}")?;
+
+    for root in building.roots.iter() {
+        writeln!(w, "root {} {}", Floats(&root.position), Floats(&root.rotation))?;
+    }
+
+    for (index, block) in building.blocks.iter().enumerate() {
+        write_block(w, index, block)?;
+    }
+
+    if !building.trailing.is_empty() {
+        writeln!(w, "trailing {}", Hex(&building.trailing))?;
+    }
+
+    Ok(())
+}
+
+fn write_block(w: &mut impl Write, index: usize, block: &Block) -> fmt::Result {
+    writeln!(w, "block {index}")?;
+    writeln!(w, "  id {}", block.id.0)?;
+    writeln!(w, "  root {}", block.root)?;
+    writeln!(w, "  position {}", Floats(&block.position))?;
+    writeln!(w, "  rotation {}", Floats(&block.rotation))?;
+    writeln!(w, "  name {}", Quoted(&block.name))?;
+    writeln!(w, "  enable_state {}", Floats(&[block.enable_state]))?;
+    writeln!(w, "  enable_state_current {}", Floats(&[block.enable_state_current]))?;
+    writeln!(w, "  connections{}", List(&block.connections))?;
+    if let Some(load) = block.load {
+        writeln!(w, "  load {load}")?;
+    }
+    if let Some(color) = block.color {
+        writeln!(w, "  color{}", List(&color))?;
+    }
+
+    let Some(metadata) = &block.metadata else {
+        return Ok(());
+    };
+    writeln!(w, "  metadata")?;
+    writeln!(w, "    toggles{}", List(&metadata.toggles))?;
+    writeln!(w, "    values {}", Floats(&metadata.values))?;
+    for field in metadata.fields.iter() {
+        writeln!(w, "    field{}", List(field))?;
+    }
+    writeln!(w, "    dropdowns{}", List(&metadata.dropdowns))?;
+    for color in metadata.colors.iter() {
+        writeln!(w, "    color {}", Floats(color))?;
+    }
+    for vector in metadata.vectors.iter() {
+        writeln!(w, "    vector {}", Floats(vector))?;
+    }
+    for gradient in metadata.gradients.iter() {
+        writeln!(w, "    gradient")?;
+        writeln!(w, "      color_keys {}", Floats(gradient.color_keys.as_flattened()))?;
+        writeln!(w, "      color_time_keys {}", Floats(&gradient.color_time_keys))?;
+        writeln!(w, "      alpha_keys {}", Floats(&gradient.alpha_keys))?;
+        writeln!(w, "      alpha_time_keys {}", Floats(&gradient.alpha_time_keys))?;
+    }
+
+    match &metadata.type_settings {
+        TypeSettings::None => {}
+        TypeSettings::MathBlock { function, incoming_connections_order, slots } => {
+            writeln!(w, "    math_block {}", Quoted(function))?;
+            writeln!(w, "    incoming_connections_order{}", List(incoming_connections_order))?;
+            writeln!(w, "    slots{}", List(slots))?;
+        }
+        TypeSettings::Raw(raw) => writeln!(w, "    raw {}", Hex(raw))?,
+    }
+
+    Ok(())
+}
+
+/// Space separated floats that parse back to the same bits.
+struct Floats<'a>(&'a [f32]);
+
+impl fmt::Display for Floats<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, value) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_char(' ')?;
+            }
+            if value.is_nan() {
+                write!(f, "NaN:{:08x}", value.to_bits())?;
+            } else {
+                write!(f, "{value:?}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Values each preceded by a space, so empty lists leave just the keyword.
+struct List<'a, T>(&'a [T]);
+
+impl<T: fmt::Display> fmt::Display for List<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|value| write!(f, " {value}"))
+    }
+}
+
+struct Hex<'a>(&'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+struct Quoted<'a>(&'a str);
+
+impl fmt::Display for Quoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_char('"')?;
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+/// Where the following lines belong.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Top,
+    Block,
+    Metadata,
+    Gradient,
+}
+
+struct Line<'a> {
+    number: usize,
+    keyword: &'a str,
+    values: Vec<String>,
+}
+
+impl Line<'_> {
+    fn invalid(&self, value: &str) -> TextError {
+        TextError::InvalidValue { line: self.number, value: value.to_string() }
+    }
+
+    fn count(&self, expected: usize) -> Result<(), TextError> {
+        if self.values.len() != expected {
+            return Err(TextError::ValueCount {
+                line: self.number,
+                keyword: self.keyword.to_string(),
+                expected,
+                found: self.values.len(),
+            });
+        }
+        Ok(())
+    }
+
+    fn parse<T: std::str::FromStr>(&self) -> Result<Vec<T>, TextError> {
+        self.values.iter().map(|v| v.parse().map_err(|_| self.invalid(v))).collect()
+    }
+
+    fn floats(&self) -> Result<Vec<f32>, TextError> {
+        self.values.iter().map(|v| parse_float(v).ok_or_else(|| self.invalid(v))).collect()
+    }
+
+    fn float_array<const N: usize>(&self) -> Result<[f32; N], TextError> {
+        self.count(N)?;
+        Ok(self.floats()?.try_into().expect("length checked"))
+    }
+
+    fn single<T: std::str::FromStr>(&self) -> Result<T, TextError> {
+        self.count(1)?;
+        Ok(self.parse()?.remove(0))
+    }
+
+    fn string(&self) -> Result<String, TextError> {
+        self.count(1)?;
+        Ok(self.values[0].clone())
+    }
+
+    fn hex(&self) -> Result<Vec<u8>, TextError> {
+        match self.values.as_slice() {
+            [] => Ok(Vec::new()),
+            [hex] if hex.len() % 2 == 0 && hex.is_ascii() => (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| self.invalid(hex)))
+                .collect(),
+            [value] => Err(self.invalid(value)),
+            _ => self.count(1).map(|_| Vec::new()),
+        }
+    }
+}
+
+fn parse_float(value: &str) -> Option<f32> {
+    match value.strip_prefix("NaN:") {
+        Some(bits) => u32::from_str_radix(bits, 16).ok().map(f32::from_bits).filter(|v| v.is_nan()),
+        None => value.parse().ok(),
+    }
+}
+
+/// Splits a line into whitespace separated values, unquoting strings.
+fn split(line: &str, number: usize) -> Result<Vec<String>, TextError> {
+    let mut values = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut value = String::new();
+        if c != '"' {
+            while let Some(&c) = chars.peek() && !c.is_whitespace() {
+                value.push(c);
+                chars.next();
+            }
+            values.push(value);
+            continue;
+        }
+
+        chars.next();
+        loop {
+            match chars.next() {
+                None => return Err(TextError::UnterminatedString { line: number }),
+                Some('"') => break,
+                Some('\\') => {
+                    let escaped = match chars.next() {
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('u') if chars.next() == Some('{') => {
+                            let code: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                            u32::from_str_radix(&code, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or(TextError::InvalidValue { line: number, value: code })?
+                        }
+                        _ => return Err(TextError::InvalidValue { line: number, value: line.to_string() }),
+                    };
+                    value.push(escaped);
+                }
+                Some(c) => value.push(c),
+            }
+        }
+        values.push(value);
+    }
+
+    Ok(values)
+}
+
+/// Reads a building from its text form.
+///
+/// # Errors
+/// Returns the first problem found, with its line number.
+pub fn from_text(text: &str) -> Result<Building, TextError> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+    match lines.next() {
+        Some((_, line)) if line == HEADER => {}
+        _ => return Err(TextError::MissingHeader),
+    }
+
+    let mut building = Building::default();
+    let mut section = Section::Top;
+
+    for (number, text) in lines {
+        let (keyword, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let line = Line { number, keyword, values: split(rest, number)? };
+        let out_of_context = || TextError::OutOfContext { line: number, keyword: keyword.to_string() };
+
+        // Section keywords.
+        match keyword {
+            "root" if section == Section::Top => {
+                line.count(6)?;
+                let values = line.floats()?;
+                building.roots.push(Root {
+                    position: [values[0], values[1], values[2]],
+                    rotation: [values[3], values[4], values[5]],
+                });
+                continue;
+            }
+            "block" => {
+                let found: usize = line.single()?;
+                if found != building.blocks.len() {
+                    return Err(TextError::BlockIndex { line: number, expected: building.blocks.len(), found });
+                }
+                building.blocks.push(Block::default());
+                section = Section::Block;
+                continue;
+            }
+            "trailing" => {
+                building.trailing = line.hex()?;
+                section = Section::Top;
+                continue;
+            }
+            "metadata" if section == Section::Block => {
+                line.count(0)?;
+                building.blocks.last_mut().expect("in a block").metadata = Some(Metadata::default());
+                section = Section::Metadata;
+                continue;
+            }
+            "gradient" if matches!(section, Section::Metadata | Section::Gradient) => {
+                line.count(0)?;
+                metadata(&mut building).gradients.push(Gradient {
+                    color_keys: Vec::new(),
+                    color_time_keys: Vec::new(),
+                    alpha_keys: Vec::new(),
+                    alpha_time_keys: Vec::new(),
+                });
+                section = Section::Gradient;
+                continue;
+            }
+            _ => {}
+        }
+
+        // A gradient ends at the first line that isn't one of its keys.
+        if section == Section::Gradient {
+            let gradient = metadata(&mut building).gradients.last_mut().expect("in a gradient");
+            match keyword {
+                "color_keys" => {
+                    let values = line.floats()?;
+                    if values.len() % 4 != 0 {
+                        return Err(line.invalid(&line.values.join(" ")));
+                    }
+                    gradient.color_keys = values.chunks(4).map(|c| [c[0], c[1], c[2], c[3]]).collect();
+                    continue;
+                }
+                "color_time_keys" => { gradient.color_time_keys = line.floats()?; continue; }
+                "alpha_keys" => { gradient.alpha_keys = line.floats()?; continue; }
+                "alpha_time_keys" => { gradient.alpha_time_keys = line.floats()?; continue; }
+                _ => section = Section::Metadata,
+            }
+        }
+
+        match section {
+            Section::Top => {
+                return Err(match keyword {
+                    "root" | "metadata" | "gradient" => out_of_context(),
+                    _ => TextError::UnknownKeyword { line: number, keyword: keyword.to_string() },
+                });
+            }
+            Section::Block => {
+                let block = building.blocks.last_mut().expect("in a block");
+                match keyword {
+                    "id" => block.id = BlockKind(line.single()?),
+                    "root" => block.root = line.single()?,
+                    "position" => block.position = line.float_array()?,
+                    "rotation" => block.rotation = line.float_array()?,
+                    "name" => block.name = line.string()?,
+                    "enable_state" => block.enable_state = line.float_array::<1>()?[0],
+                    "enable_state_current" => block.enable_state_current = line.float_array::<1>()?[0],
+                    "connections" => block.connections = line.parse()?,
+                    "load" => block.load = Some(line.single()?),
+                    "color" => {
+                        line.count(4)?;
+                        block.color = Some(line.parse::<u8>()?.try_into().expect("length checked"));
+                    }
+                    "gradient" => return Err(out_of_context()),
+                    _ => return Err(TextError::UnknownKeyword { line: number, keyword: keyword.to_string() }),
+                }
+            }
+            Section::Metadata | Section::Gradient => {
+                let metadata = metadata(&mut building);
+                match keyword {
+                    "toggles" => metadata.toggles = line.parse()?,
+                    "values" => metadata.values = line.floats()?,
+                    "field" => metadata.fields.push(line.parse()?),
+                    "dropdowns" => metadata.dropdowns = line.parse()?,
+                    "color" => metadata.colors.push(line.float_array()?),
+                    "vector" => metadata.vectors.push(line.float_array()?),
+                    "math_block" => {
+                        metadata.type_settings = TypeSettings::MathBlock {
+                            function: line.string()?,
+                            incoming_connections_order: Vec::new(),
+                            slots: Vec::new(),
+                        };
+                    }
+                    "incoming_connections_order" | "slots" => {
+                        let TypeSettings::MathBlock { incoming_connections_order, slots, .. } = &mut metadata.type_settings else {
+                            return Err(out_of_context());
+                        };
+                        let target = if keyword == "slots" { slots } else { incoming_connections_order };
+                        *target = line.parse()?;
+                    }
+                    "raw" => metadata.type_settings = TypeSettings::Raw(line.hex()?),
+                    "root" => return Err(out_of_context()),
+                    _ => return Err(TextError::UnknownKeyword { line: number, keyword: keyword.to_string() }),
+                }
+            }
+        }
+    }
+
+    Ok(building)
+}
+
+fn metadata(building: &mut Building) -> &mut Metadata {
+    building
+        .blocks
+        .last_mut()
+        .and_then(|b| b.metadata.as_mut())
+        .expect("in a metadata section")
+}
+
+#[test]
+fn test_text_roundtrip() {
+    use crate::io::{ReadBuilding, ReadOptions, WriteBuilding};
+
+    let mut building = Building::default();
+    building.roots.push(Root { position: [1.5, -0.0, 1e-30], rotation: [0.0, 90.0, 0.0] });
+    building.roots.push(Root::default());
+    building.blocks.push(Block {
+        name: "Quote \" \\ and\nnewline \u{1}".to_string(),
+        position: [0.1, 0.2, 0.3],
+        connections: vec![1],
+        load: Some(1),
+        color: Some([248, 252, 248, 255]),
+        enable_state: f32::from_bits(0x7fc0_0001),
+        ..Default::default()
+    });
+    building.blocks.push(Block {
+        id: BlockKind::MATH_BLOCK,
+        root: 1,
+        metadata: Some(Metadata {
+            toggles: vec![true, false],
+            values: vec![0.25, f32::INFINITY],
+            fields: vec![vec![1, -2], vec![]],
+            dropdowns: vec![7],
+            colors: vec![[1.0, 0.0, 0.0, 1.0]],
+            gradients: vec![Gradient {
+                color_keys: vec![[0.0, 0.0, 0.0, 1.0], [1.0, 1.0, 1.0, 1.0]],
+                color_time_keys: vec![0.0, 1.0],
+                alpha_keys: vec![1.0],
+                alpha_time_keys: vec![0.0],
+            }],
+            vectors: vec![[1.0, 2.0, 3.0]],
+            type_settings: TypeSettings::MathBlock {
+                function: "a + b".to_string(),
+                incoming_connections_order: vec![0],
+                slots: vec![1],
+            },
+        }),
+        ..Default::default()
+    });
+    building.blocks.push(Block {
+        id: BlockKind(109),
+        metadata: Some(Metadata { type_settings: TypeSettings::Raw(vec![0xde, 0xad]), ..Default::default() }),
+        ..Default::default()
+    });
+    building.trailing = vec![0xab, 0xcd];
+
+    let text = to_text(&building);
+    let loaded = from_text(&text).unwrap();
+    assert_eq!(to_text(&loaded), text);
+    assert_eq!(loaded.blocks[0].enable_state.to_bits(), 0x7fc0_0001);
+    assert_eq!(loaded.blocks[1], building.blocks[1]);
+    assert_eq!(loaded.blocks[2], building.blocks[2]);
+
+    // binary -> text -> binary writes the same bytes as binary -> binary. Math and
+    // custom block settings can't be read from binary, so they are left out.
+    let mut binary = building.clone();
+    binary.blocks[1].id = BlockKind(40);
+    binary.blocks[2].metadata = None;
+    for version in [0, 6] {
+        let mut buffer = Vec::new();
+        buffer.write_building(&binary, version).unwrap();
+        let read = (&buffer[..]).read_building_with(&ReadOptions { trailing: true }).unwrap();
+
+        let mut direct = Vec::new();
+        direct.write_building(&read, version).unwrap();
+        let mut rewritten = Vec::new();
+        rewritten.write_building(&from_text(&to_text(&read)).unwrap(), version).unwrap();
+        assert_eq!(direct, rewritten, "version {version}");
+    }
+
+    assert_eq!(from_text("root 0 0 0 0 0 0"), Err(TextError::MissingHeader));
+    assert_eq!(
+        from_text(&format!("{HEADER}\nblock 1")),
+        Err(TextError::BlockIndex { line: 2, expected: 0, found: 1 })
+    );
+    assert_eq!(
+        from_text(&format!("{HEADER}\nblock 0\n  position 1 2")),
+        Err(TextError::ValueCount { line: 3, keyword: "position".to_string(), expected: 3, found: 2 })
+    );
+    assert!(matches!(from_text(&format!("{HEADER}\nmetadata")), Err(TextError::OutOfContext { line: 2, .. })));
+}
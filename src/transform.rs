@@ -0,0 +1,140 @@
+//! Geometric transforms of buildings and selections of blocks.
+//!
+//! A [`Transform`] maps world-space positions and rotations. Applying it to a
+//! building updates every block and root, applying it to a selection only
+//! updates the selected blocks and leaves the roots in place.
+//!
+//! ```rust
+//! use sw_structure_io::structs::*;
+//! use sw_structure_io::transform::{Axis, Transform};
+//!
+//! let mut building = Building::default();
+//! building.roots.push(Root::default());
+//! building.blocks.push(Block { position: [1.0, 0.0, 0.0], ..Default::default() });
+//!
+//! building.transform(&Transform::Mirror { pivot: [0.0; 3], axis: Axis::X });
+//! assert_eq!(building.blocks[0].position, [-1.0, 0.0, 0.0]);
+//! ```
+
+use crate::rotation;
+use crate::structs::Building;
+
+pub use crate::rotation::Axis;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transform {
+    /// Moves everything by an offset.
+    Translate([f32; 3]),
+
+    /// Rotates everything around `pivot`. The rotation uses the same Euler
+    /// convention as blocks (see [`crate::rotation`]).
+    Rotate {
+        pivot: [f32; 3],
+        rotation: [f32; 3],
+    },
+
+    /// Scales distances from `pivot` by `factor`. Blocks keep their size and
+    /// rotation, only their positions move apart or together.
+    Scale {
+        pivot: [f32; 3],
+        factor: f32,
+    },
+
+    /// Mirrors everything across the plane through `pivot` orthogonal to `axis`.
+    ///
+    /// Rotations are mirrored too, so a block facing along `axis` faces the other
+    /// way afterwards. The game has no mirrored blocks, so blocks that aren't
+    /// symmetric end up as rotated copies, not as mirror images.
+    Mirror {
+        pivot: [f32; 3],
+        axis: Axis,
+    },
+}
+
+impl Transform {
+    /// Returns the transformed position.
+    pub fn apply_position(&self, position: [f32; 3]) -> [f32; 3] {
+        match *self {
+            Transform::Translate(offset) => std::array::from_fn(|i| position[i] + offset[i]),
+            Transform::Rotate { pivot, rotation } => {
+                let relative = std::array::from_fn(|i| position[i] - pivot[i]);
+                let rotated = rotation::transform(rotation::euler_to_matrix(rotation), relative);
+                std::array::from_fn(|i| pivot[i] + rotated[i])
+            }
+            Transform::Scale { pivot, factor } => {
+                std::array::from_fn(|i| pivot[i] + (position[i] - pivot[i]) * factor)
+            }
+            Transform::Mirror { pivot, axis } => {
+                let mut position = position;
+                let i = axis.index();
+                position[i] = 2.0 * pivot[i] - position[i];
+                position
+            }
+        }
+    }
+
+    /// Returns the transformed rotation.
+    pub fn apply_rotation(&self, euler: [f32; 3]) -> [f32; 3] {
+        match *self {
+            Transform::Translate(_) | Transform::Scale { .. } => euler,
+            Transform::Rotate { rotation, .. } => rotation::compose(rotation, euler),
+            Transform::Mirror { axis, .. } => rotation::mirror(euler, axis),
+        }
+    }
+}
+
+impl Building {
+    /// Applies a transform to all blocks and roots.
+    pub fn transform(&mut self, transform: &Transform) {
+        for block in self.blocks.iter_mut() {
+            block.position = transform.apply_position(block.position);
+            block.rotation = transform.apply_rotation(block.rotation);
+        }
+        for root in self.roots.iter_mut() {
+            root.position = transform.apply_position(root.position);
+            root.rotation = transform.apply_rotation(root.rotation);
+        }
+    }
+
+    /// Applies a transform to the given blocks only. Roots are left unchanged.
+    ///
+    /// # Panics
+    /// Panics if any of the blocks doesn't exist.
+    pub fn transform_blocks(&mut self, blocks: &[usize], transform: &Transform) {
+        for &index in blocks {
+            let block = &mut self.blocks[index];
+            block.position = transform.apply_position(block.position);
+            block.rotation = transform.apply_rotation(block.rotation);
+        }
+    }
+}
+
+#[test]
+fn test_transform() {
+    use crate::structs::*;
+
+    let close = |a: [f32; 3], b: [f32; 3]| a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-4);
+
+    let mut building = Building::default();
+    building.roots.push(Root { position: [1.0, 0.0, 0.0], ..Default::default() });
+    building.blocks.push(Block { position: [2.0, 0.0, 0.0], ..Default::default() });
+    building.blocks.push(Block { position: [0.0, 0.0, 1.0], ..Default::default() });
+
+    building.transform(&Transform::Translate([0.0, 1.0, 0.0]));
+    assert_eq!(building.blocks[0].position, [2.0, 1.0, 0.0]);
+    assert_eq!(building.roots[0].position, [1.0, 1.0, 0.0]);
+
+    building.transform(&Transform::Rotate { pivot: [1.0, 1.0, 0.0], rotation: [0.0, 90.0, 0.0] });
+    assert!(close(building.blocks[0].position, [1.0, 1.0, -1.0]));
+    assert!(close(building.blocks[0].rotation, [0.0, 90.0, 0.0]));
+    assert!(close(building.roots[0].position, [1.0, 1.0, 0.0]));
+
+    building.transform_blocks(&[1], &Transform::Scale { pivot: [0.0; 3], factor: 2.0 });
+    assert!(close(building.blocks[1].position, [4.0, 2.0, 2.0]));
+    assert!(close(building.blocks[1].rotation, [0.0, 90.0, 0.0]));
+    assert_eq!(building.blocks[0].position[2], -1.0);
+
+    building.transform(&Transform::Mirror { pivot: [0.0; 3], axis: Axis::X });
+    assert!(close(building.blocks[0].position, [-1.0, 1.0, -1.0]));
+    assert!(close(building.blocks[0].rotation, [0.0, -90.0, 0.0]));
+}
@@ -0,0 +1,372 @@
+//! Consistency checks for buildings.
+//!
+//! The structs in [`crate::structs`] are plain data and can describe buildings the
+//! game would reject, e.g. blocks attached to roots that don't exist.
+//! [`Building::validate`] finds such problems before the building is serialized,
+//! [`Building::validate_for_version`] also what a format version can't store.
+
+use std::fmt;
+
+use crate::catalog::TypeSettingsKind;
+use crate::io::version;
+use crate::math::ParseError;
+use crate::structs::{Block, Building, TypeSettings};
+
+/// A single problem found by [`Building::validate`] or
+/// [`Building::validate_for_version`].
+///
+/// Block, root and connection values are indices into the building's vectors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// The block is attached to a root that doesn't exist.
+    RootOutOfRange {
+        block: usize,
+        root: u16,
+    },
+
+    /// The block is connected to a block that doesn't exist.
+    ConnectionOutOfRange {
+        block: usize,
+        connection: u16,
+    },
+
+    /// The block's load refers to a block that doesn't exist.
+    LoadOutOfRange {
+        block: usize,
+        load: u16,
+    },
+
+    /// The block's load refers to a block of the same root. Loads attach blocks
+    /// of different roots, so this is never valid.
+    LoadInSameRoot {
+        block: usize,
+        load: u16,
+    },
+
+    /// The math block's `incoming_connections_order` and `slots` have different
+    /// lengths, so some connections have no slot assigned.
+    MathBlockSlotsMismatch {
+        block: usize,
+        incoming_connections: usize,
+        slots: usize,
+    },
+
+    /// The math block takes an input from a block that doesn't exist.
+    MathInputOutOfRange {
+        block: usize,
+        input: u8,
+    },
+
+    /// The building has more roots or blocks than the format can count.
+    CountOverflow {
+        field: &'static str,
+        count: usize,
+        max: usize,
+    },
+
+    /// A list of the block is longer than the format can store.
+    LengthOverflow {
+        block: usize,
+        field: &'static str,
+        len: usize,
+        max: usize,
+    },
+
+    /// The block's root index doesn't fit into the format (v0 stores it in a byte).
+    RootIndexOverflow {
+        block: usize,
+        root: u16,
+        max: usize,
+    },
+
+    /// A math block input ends up at a block index that doesn't fit into a byte
+    /// (v6 stores blocks grouped by root).
+    MathInputOverflow {
+        block: usize,
+        input: u8,
+        index: usize,
+    },
+
+    /// The math block's function is not a valid expression, see
+    /// [`Building::lint_math`].
+    MathBlockSyntax {
+        block: usize,
+        error: ParseError,
+    },
+}
+
+impl Diagnostic {
+    /// Index of the block the diagnostic is about, `None` if it is about the
+    /// whole building.
+    pub fn block(&self) -> Option<usize> {
+        match *self {
+            Diagnostic::RootOutOfRange { block, .. }
+            | Diagnostic::ConnectionOutOfRange { block, .. }
+            | Diagnostic::LoadOutOfRange { block, .. }
+            | Diagnostic::LoadInSameRoot { block, .. }
+            | Diagnostic::MathBlockSlotsMismatch { block, .. }
+            | Diagnostic::MathInputOutOfRange { block, .. }
+            | Diagnostic::LengthOverflow { block, .. }
+            | Diagnostic::RootIndexOverflow { block, .. }
+            | Diagnostic::MathInputOverflow { block, .. }
+            | Diagnostic::MathBlockSyntax { block, .. } => Some(block),
+            Diagnostic::CountOverflow { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::RootOutOfRange { block, root } => {
+                write!(f, "block {block} is attached to root {root}, which doesn't exist")
+            }
+            Diagnostic::ConnectionOutOfRange { block, connection } => {
+                write!(f, "block {block} is connected to block {connection}, which doesn't exist")
+            }
+            Diagnostic::LoadOutOfRange { block, load } => {
+                write!(f, "block {block} loads block {load}, which doesn't exist")
+            }
+            Diagnostic::LoadInSameRoot { block, load } => {
+                write!(f, "block {block} loads block {load} of the same root")
+            }
+            Diagnostic::MathBlockSlotsMismatch { block, incoming_connections, slots } => {
+                write!(
+                    f,
+                    "math block {block} orders {incoming_connections} incoming connections but assigns {slots} slots"
+                )
+            }
+            Diagnostic::MathInputOutOfRange { block, input } => {
+                write!(f, "math block {block} takes an input from block {input}, which doesn't exist")
+            }
+            Diagnostic::CountOverflow { field, count, max } => {
+                write!(f, "the building has {count} {field}, but at most {max} can be stored")
+            }
+            Diagnostic::LengthOverflow { block, field, len, max } => {
+                write!(f, "block {block} has {len} {field}, but at most {max} can be stored")
+            }
+            Diagnostic::RootIndexOverflow { block, root, max } => {
+                write!(f, "block {block} is attached to root {root}, but at most root {max} can be stored")
+            }
+            Diagnostic::MathInputOverflow { block, input, index } => {
+                write!(f, "math block {block} takes an input from block {input}, which is stored at index {index}")
+            }
+            Diagnostic::MathBlockSyntax { block, error } => {
+                write!(f, "math block {block} has an invalid function: {error}")
+            }
+        }
+    }
+}
+
+impl Building {
+    /// Checks that all indices in the building are consistent.
+    ///
+    /// Returns every problem found, an empty vector means the building is valid.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            if block.root as usize >= self.roots.len() {
+                diagnostics.push(Diagnostic::RootOutOfRange { block: index, root: block.root });
+            }
+
+            for &connection in block.connections.iter() {
+                if connection as usize >= self.blocks.len() {
+                    diagnostics.push(Diagnostic::ConnectionOutOfRange { block: index, connection });
+                }
+            }
+
+            if let Some(load) = block.load {
+                match self.blocks.get(load as usize) {
+                    None => diagnostics.push(Diagnostic::LoadOutOfRange { block: index, load }),
+                    Some(loaded) if loaded.root == block.root => {
+                        diagnostics.push(Diagnostic::LoadInSameRoot { block: index, load })
+                    }
+                    Some(_) => {}
+                }
+            }
+
+            if let Some(metadata) = &block.metadata
+                && let TypeSettings::MathBlock { incoming_connections_order, slots, .. } = &metadata.type_settings
+            {
+                if incoming_connections_order.len() != slots.len() {
+                    diagnostics.push(Diagnostic::MathBlockSlotsMismatch {
+                        block: index,
+                        incoming_connections: incoming_connections_order.len(),
+                        slots: slots.len(),
+                    });
+                }
+                for &input in incoming_connections_order.iter() {
+                    if input as usize >= self.blocks.len() {
+                        diagnostics.push(Diagnostic::MathInputOutOfRange { block: index, input });
+                    }
+                }
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Checks the building like [`Building::validate`], and additionally for
+    /// everything the writer of `version` can't store.
+    ///
+    /// An empty result means writing the building in `version` succeeds. This
+    /// is the check [`WriteOptions::validate`](crate::io::WriteOptions::validate) runs.
+    pub fn validate_for_version(&self, version: u8) -> Vec<Diagnostic> {
+        const MAX: usize = u16::MAX as usize;
+
+        let mut diagnostics = self.validate();
+
+        for (field, count) in [("roots", self.roots.len()), ("blocks", self.blocks.len())] {
+            if count > MAX {
+                diagnostics.push(Diagnostic::CountOverflow { field, count, max: MAX });
+            }
+        }
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            for (field, len, max) in lengths(block) {
+                if len > max {
+                    diagnostics.push(Diagnostic::LengthOverflow { block: index, field, len, max });
+                }
+            }
+
+            if version == 0 && block.root > u8::MAX as u16 {
+                diagnostics.push(Diagnostic::RootIndexOverflow { block: index, root: block.root, max: u8::MAX as usize });
+            }
+        }
+
+        if version == 6 {
+            let mut new_index = vec![0usize; self.blocks.len()];
+            for (new, &old) in version::v6::block_order(self).iter().enumerate() {
+                new_index[old] = new;
+            }
+            for (index, block) in self.blocks.iter().enumerate() {
+                if let Some(metadata) = &block.metadata
+                    && let TypeSettings::MathBlock { incoming_connections_order, .. } = &metadata.type_settings
+                {
+                    for &input in incoming_connections_order.iter() {
+                        if let Some(&new) = new_index.get(input as usize)
+                            && new > u8::MAX as usize
+                        {
+                            diagnostics.push(Diagnostic::MathInputOverflow { block: index, input, index: new });
+                        }
+                    }
+                }
+            }
+        }
+
+        diagnostics
+    }
+}
+
+/// Lengths of the lists of a block that are stored with a length prefix, as
+/// `(name, length, maximum)`.
+fn lengths(block: &Block) -> Vec<(&'static str, usize, usize)> {
+    const MAX: usize = u16::MAX as usize;
+
+    let mut lengths = vec![("connections", block.connections.len(), MAX)];
+    let Some(metadata) = &block.metadata else {
+        return lengths;
+    };
+
+    lengths.extend([
+        ("toggles", metadata.toggles.len(), MAX),
+        ("values", metadata.values.len(), MAX),
+        // The top bit of the fields count flags the vectors.
+        ("fields", metadata.fields.len(), 0x7FFF),
+        ("vectors", metadata.vectors.len(), MAX),
+        ("dropdowns", metadata.dropdowns.len(), MAX),
+        ("colors", metadata.colors.len(), MAX),
+        ("gradients", metadata.gradients.len(), MAX),
+    ]);
+    lengths.extend(metadata.fields.iter().map(|field| ("field slots", field.len(), MAX)));
+    for gradient in metadata.gradients.iter() {
+        lengths.extend([
+            ("gradient color keys", gradient.color_keys.len(), MAX),
+            ("gradient color time keys", gradient.color_time_keys.len(), MAX),
+            ("gradient alpha keys", gradient.alpha_keys.len(), MAX),
+            ("gradient alpha time keys", gradient.alpha_time_keys.len(), MAX),
+        ]);
+    }
+    if block.id.type_settings_kind() == TypeSettingsKind::MathBlock
+        && let TypeSettings::MathBlock { function, .. } = &metadata.type_settings
+    {
+        lengths.push(("function bytes", function.len(), MAX));
+    }
+
+    lengths
+}
+
+#[test]
+fn test_validate() {
+    use crate::io::{ErrorKind, WriteBuilding, WriteOptions};
+    use crate::structs::*;
+
+    let mut building = Building::default();
+    building.roots.push(Root::default());
+    building.roots.push(Root::default());
+    building.blocks.push(Block { connections: vec![1], load: Some(1), ..Default::default() });
+    building.blocks.push(Block { root: 1, ..Default::default() });
+    assert!(building.validate().is_empty());
+
+    building.blocks.push(Block {
+        root: 2,
+        connections: vec![0, 5],
+        load: Some(2),
+        metadata: Some(Metadata {
+            type_settings: TypeSettings::MathBlock {
+                function: String::new(),
+                incoming_connections_order: vec![0, 1],
+                slots: vec![0],
+            },
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+
+    assert_eq!(building.validate(), vec![
+        Diagnostic::RootOutOfRange { block: 2, root: 2 },
+        Diagnostic::ConnectionOutOfRange { block: 2, connection: 5 },
+        Diagnostic::LoadInSameRoot { block: 2, load: 2 },
+        Diagnostic::MathBlockSlotsMismatch { block: 2, incoming_connections: 2, slots: 1 },
+    ]);
+
+    // What the writers can't store is reported before anything is written.
+    let mut building = Building::default();
+    building.roots.resize_with(0x101, Root::default);
+    building.blocks.resize_with(0x101, Block::default);
+    building.blocks[0].root = 0x100;
+    building.blocks[1].connections = vec![0; 0x10000];
+    building.blocks[2].metadata = Some(Metadata {
+        type_settings: TypeSettings::MathBlock {
+            function: "a".to_string(),
+            incoming_connections_order: vec![1],
+            slots: vec![0],
+        },
+        ..Default::default()
+    });
+    assert!(building.validate().is_empty());
+
+    let overflow = Diagnostic::LengthOverflow { block: 1, field: "connections", len: 0x10000, max: 0xFFFF };
+    assert_eq!(building.validate_for_version(0), vec![
+        Diagnostic::RootIndexOverflow { block: 0, root: 0x100, max: 0xFF },
+        overflow.clone(),
+    ]);
+    // v6 groups blocks by root, so block 0 (root 0x100) is stored last, at index
+    // 0x100, and block 1 moves to index 0.
+    assert_eq!(building.validate_for_version(6), vec![overflow]);
+    building.blocks[2].metadata = Some(Metadata {
+        type_settings: TypeSettings::MathBlock {
+            function: "a".to_string(),
+            incoming_connections_order: vec![0],
+            slots: vec![0],
+        },
+        ..Default::default()
+    });
+    assert_eq!(building.validate_for_version(6)[1], Diagnostic::MathInputOverflow { block: 2, input: 0, index: 0x100 });
+
+    let mut buffer = Vec::new();
+    let e = buffer.write_building_with(&building, &WriteOptions { validate: true, ..WriteOptions::new(0) }).unwrap_err();
+    assert!(matches!(e.kind, ErrorKind::InvalidBuilding { .. }));
+    assert!(buffer.is_empty());
+}